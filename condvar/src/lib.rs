@@ -1,4 +1,5 @@
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
 
 ///////////////////////////////////////////////////////////////////////////////
 #[derive(Clone)]
@@ -53,6 +54,17 @@ impl<T> OptionalConditionalVariable<T> {
         (*current).take().unwrap()
     }
 
+    /// Returns None if no value was set within the timeout
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Box<T>> {
+        let clone = self.inner.clone();
+        let (lock, cvar) = &*clone;
+        let current = lock.lock().unwrap();
+        let (mut current, _) = cvar
+            .wait_timeout_while(current, timeout, |current| (*current).is_none())
+            .unwrap();
+        (*current).take()
+    }
+
     pub fn notify(&mut self, new_value: T) {
         let clone = self.inner.clone();
         let (lock, cvar) = &*clone;
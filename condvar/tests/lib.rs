@@ -107,3 +107,18 @@ fn option_conditional_var_struct() {
 
     assert_eq!(var.wait().val, 7);
 }
+#[test]
+fn option_conditional_var_timeout() {
+    let var = OptionalConditionalVariable::<u32>::new();
+    let mut var_clone = var.clone();
+
+    assert!(var.wait_timeout(Duration::from_millis(50)).is_none());
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        var_clone.notify(8);
+    });
+    assert_eq!(
+        var.wait_timeout(Duration::from_secs(5)).map(|value| *value),
+        Some(8)
+    );
+}
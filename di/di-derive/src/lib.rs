@@ -4,133 +4,397 @@ use proc_macro::{self, TokenStream};
 use quote::{quote, ToTokens};
 use syn::{
     parse::{Parse, ParseStream},
-    parse_macro_input,
-    punctuated::Punctuated,
-    FnArg, Ident, Item, Pat, Result, ReturnType, Token, Type,
+    parse_macro_input, Expr, FnArg, GenericArgument, Ident, Item, ItemStruct, Pat, Path,
+    PathArguments, Result, ReturnType, Token, Type, TypeParamBound,
 };
 
+/// Arguments of #[inject(a, b)] or #[inject(registry = expr, a, b)]
 struct Args {
     pub vars: HashSet<Ident>,
+    /// container the services are resolved from, the global one if None
+    pub registry: Option<Expr>,
 }
 
 impl Parse for Args {
     fn parse(input: ParseStream) -> Result<Self> {
-        let vars = Punctuated::<Ident, Token![,]>::parse_terminated(input)?;
-        Ok(Args {
-            vars: vars.into_iter().collect(),
-        })
+        let mut vars = HashSet::new();
+        let mut registry = None;
+        while !input.is_empty() {
+            let ident: Ident = input.parse()?;
+            if input.peek(Token![=]) {
+                input.parse::<Token![=]>()?;
+                if ident != "registry" {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        format!("unknown inject option {}", ident),
+                    ));
+                }
+                registry = Some(input.parse::<Expr>()?);
+            } else {
+                vars.insert(ident);
+            }
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        Ok(Args { vars, registry })
     }
 }
 
+/// Resolves the listed parameters as services and removes them from the
+/// signature. The function returns a Result with the service errors. The
+/// services of an async function are locked with read_async()/write_async()
+/// and stay locked until the function returns.
+///
+/// A service parameter is one of &dyn T, &mut dyn T, ServiceRef<dyn T>,
+/// ServiceMut<dyn T>, ServiceHandle<dyn T> or Arc<dyn T>. The handle is not
+/// locked, it is the shared, owned reference to the service. The Arc is not
+/// locked either, so only Service::shareable() services are injected as Arc,
+/// see ServiceHandle::shared().
 #[proc_macro_attribute]
 pub fn inject(attr: TokenStream, item: TokenStream) -> TokenStream {
     // read arguments to substitute
-    let mut injected_args: Args = parse_macro_input!(attr);
-    injected_args
-        .vars
-        .iter()
-        .for_each(|_arg| () /*println!("{}", arg)*/);
-
-    let mut function_signature = quote!();
-    let mut injected_services = quote!();
-    let mut function_block = quote!();
-    let mut session = quote!(&di::registry::SimpleSession::default());
-
-    // read function
+    let injected_args: Args = parse_macro_input!(attr);
     let function = parse_macro_input!(item as Item);
-    match function {
-        Item::Fn(func) => {
-            let mut parameter = quote!();
-            let mut num_parameter = 0;
-            func.sig.inputs.iter().for_each(|param| match param {
-                FnArg::Receiver(_recv) => {
-                    //println!("{}", recv.to_token_stream());
-                }
-                FnArg::Typed(typ) => {
-                    let param_name = match typ.pat.as_ref() {
-                        Pat::Ident(ident) => &ident.ident,
-                        _ => panic!("[inject(parameter)]: Unsupported pat type"),
-                    };                    
-
-                    let mut is_session = false;
-                    typ.attrs.iter().for_each(|arg| {
-                        is_session = match arg.path.get_ident() {
-                            Some(ident) => ident.to_string().eq("session"),
-                            None => false,
-                        };
-                    });
-
-                    let typref = match typ.ty.as_ref() {
-                        Type::Reference(reference) => Some(reference),
-                        _ => None, //panic!("[inject(parameter)]: Unsupported ty type"),
-                    };
+    match expand_inject(injected_args, function) {
+        Ok(output) => output.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
 
-                    if !injected_args.vars.contains(param_name) {
-                        parameter = quote!(#parameter);
-                        if num_parameter > 0 {
-                            parameter = quote!(#parameter, );                            
-                        }
-                        num_parameter += 1;
+/// How an injected parameter receives its service
+enum Access {
+    /// &dyn T
+    Ref,
+    /// &mut dyn T
+    Mut,
+    /// ServiceRef<dyn T>
+    ReadGuard,
+    /// ServiceMut<dyn T>
+    WriteGuard,
+    /// ServiceHandle<dyn T>
+    Handle,
+    /// Arc<dyn T>
+    Shared,
+}
 
-                        if is_session {
-                            session = quote!(#param_name);
-                            parameter = quote!(#parameter #param_name :&dyn di::registry::Session);
-                        }else {
-                            parameter = quote!(#parameter #typ);
-                        }
-                        
-                    } else if typref.is_some() {                  
-                    
-                        let typref = typref.unwrap();
-                        let elem = &typref.elem; 
-                        let mutability = typref.mutability;
-
-                        let as_ptr = match mutability {
-                            Some(_) => quote!(#param_name.as_mut().query_mut::<#elem>().ok_or_else(|| ::error::Error::new(::di::registry::ErrorCode::Unimplemented, format!("Service of type {} not implemented", stringify!(#elem)).as_str()))?;),
-                            None => quote!(#param_name.as_ref().query_ref::<#elem>().ok_or_else(|| ::error::Error::new(::di::registry::ErrorCode::Unimplemented, format!("Service of type {} not implemented", stringify!(#elem)).as_str()))?;),
-                        };
-
-
-                        injected_services = quote!(#injected_services
-                        let #param_name = di::registry::Registry::get_service::<#elem>(#session)?; 
-                        let #param_name = #param_name.clone();
-                        let #mutability #param_name = #param_name.lock().unwrap();
-                        let #param_name = #as_ptr;
-                        );
-                    }
-                    injected_args.vars.remove(param_name);
-                }
-            });
-            
-            injected_args.vars.iter().for_each(|arg| {
-                //Diagnostic::new(Level::Warning, format!("unused injected parameter {}", arg.to_string()));
-                panic!("unused injected parameter {}", arg.to_string()); 
-            });
-
-            let visibility = func.vis.to_token_stream();
-            let name = func.sig.ident;
-            let generics = func.sig.generics;
-            let output = match func.sig.output {
-                ReturnType::Default => quote!(()),
-                ReturnType::Type(_a, b) => b.to_token_stream(),
-            };
-            function_signature = quote! {
-                #visibility fn #name #generics (#parameter) -> std::result::Result<#output, ::error::Error<::di::registry::ErrorCode>>
+/// Service trait and access of an injected parameter type
+fn injected_type(ty: &Type) -> Result<(&Type, Access)> {
+    let unsupported = || {
+        syn::Error::new_spanned(
+            ty,
+            "[inject(parameter)]: injected parameters must be &dyn T, &mut dyn T, \
+             ServiceRef<dyn T>, ServiceMut<dyn T>, ServiceHandle<dyn T> or Arc<dyn T>",
+        )
+    };
+    let path = match ty {
+        Type::Reference(reference) => {
+            let access = match reference.mutability {
+                Some(_) => Access::Mut,
+                None => Access::Ref,
             };
-            function_block = func.block.to_token_stream();
+            return Ok((&reference.elem, access));
+        }
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return Err(unsupported()),
+    };
+    let segment = path.segments.last().ok_or_else(unsupported)?;
+    let access = match segment.ident.to_string().as_str() {
+        "ServiceRef" => Access::ReadGuard,
+        "ServiceMut" => Access::WriteGuard,
+        "ServiceHandle" => Access::Handle,
+        "Arc" => Access::Shared,
+        _ => return Err(unsupported()),
+    };
+    let service = match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => {
+            arguments.args.iter().find_map(|argument| match argument {
+                GenericArgument::Type(service) => Some(service),
+                _ => None,
+            })
+        }
+        _ => None,
+    };
+    Ok((service.ok_or_else(unsupported)?, access))
+}
+
+fn expand_inject(mut injected_args: Args, function: Item) -> Result<proc_macro2::TokenStream> {
+    let func = match function {
+        Item::Fn(func) => func,
+        other => {
+            return Err(syn::Error::new_spanned(
+                other,
+                "[inject(parameter)]: Macro only implemented for functions and methods",
+            ))
+        }
+    };
+    let registry = injected_args.registry.take();
+    // async functions await the service locks, so a service in use by
+    // another task does not block the executor thread
+    let asyncness = func.sig.asyncness;
+
+    let mut session = quote!(&di::registry::SimpleSession::default());
+    let mut parameters = Vec::new();
+    let mut injected = Vec::new();
+    for param in func.sig.inputs.iter() {
+        let typ = match param {
+            FnArg::Receiver(recv) => {
+                parameters.push(recv.to_token_stream());
+                continue;
+            }
+            FnArg::Typed(typ) => typ,
+        };
+        let param_name = match typ.pat.as_ref() {
+            Pat::Ident(ident) => Some(&ident.ident),
+            _ => None,
+        };
+        let is_session = typ.attrs.iter().any(|arg| arg.path.is_ident("session"));
+
+        match param_name {
+            Some(param_name) if injected_args.vars.remove(param_name) => {
+                injected.push((param_name, typ, injected_type(&typ.ty)?));
+            }
+            Some(param_name) if is_session => {
+                session = quote!(#param_name);
+                parameters.push(quote!(#param_name: &dyn di::registry::Session));
+            }
+            None if is_session => {
+                return Err(syn::Error::new_spanned(
+                    &typ.pat,
+                    "[inject(parameter)]: the session must be a named parameter",
+                ))
+            }
+            _ => parameters.push(typ.to_token_stream()),
         }
-        _ => panic!("[inject(parameter)]: Macro only implemented for functions"),
     }
 
-    let output = quote! {
-        #function_signature{
-            #injected_services
+    if let Some(arg) = injected_args.vars.iter().next() {
+        return Err(syn::Error::new(
+            arg.span(),
+            format!("[inject(parameter)]: unused injected parameter {}", arg),
+        ));
+    }
+
+    let injected_services = injected.iter().map(|(param_name, typ, (elem, access))| {
+        let (pat, ty) = (&typ.pat, &typ.ty);
+        let get_service = match &registry {
+            Some(registry) => quote!((#registry).get_service::<#elem>(#session)?),
+            None => quote!(di::registry::Registry::global().get_service::<#elem>(#session)?),
+        };
+        let (read, write, shared) = match asyncness {
+            Some(_) => (
+                quote!(read_async().await?),
+                quote!(write_async().await?),
+                quote!(shared_async().await?),
+            ),
+            None => (quote!(read()?), quote!(write()?), quote!(shared()?)),
+        };
+        let access = match access {
+            Access::Ref => quote! {
+                let #param_name = #param_name.#read;
+                let #param_name = &*#param_name;
+            },
+            Access::Mut => quote! {
+                let mut #param_name = #param_name.#write;
+                let #param_name = &mut *#param_name;
+            },
+            Access::ReadGuard => quote!(let #pat: #ty = #param_name.#read;),
+            Access::WriteGuard => quote!(let #pat: #ty = #param_name.#write;),
+            Access::Handle => quote!(let #pat: #ty = #param_name;),
+            Access::Shared => quote!(let #pat: #ty = #param_name.#shared;),
+        };
+        quote! {
+            let #param_name = #get_service;
+            #access
+        }
+    });
+
+    let attrs = &func.attrs;
+    let visibility = &func.vis;
+    let constness = &func.sig.constness;
+    let unsafety = &func.sig.unsafety;
+    let abi = &func.sig.abi;
+    let name = &func.sig.ident;
+    let generics = &func.sig.generics;
+    let where_clause = &func.sig.generics.where_clause;
+    let output = match &func.sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_a, b) => b.to_token_stream(),
+    };
+    let variadic = &func.sig.variadic;
+    let function_block = &func.block;
+
+    Ok(quote! {
+        #(#attrs)*
+        #visibility #constness #asyncness #unsafety #abi fn #name #generics (#(#parameters,)* #variadic)
+            -> std::result::Result<#output, ::error::Error<::di::registry::ErrorCode>>
+            #where_clause
+        {
+            #(#injected_services)*
             let result = #function_block;
             Ok(result)
         }
+    })
+}
+
+/// Arguments of
+/// #[service(provides = dyn Trait, lifetime = scoped, factory = path, hooks)]
+struct ServiceArgs {
+    pub provides: Type,
+    /// name of the di::service::Lifetime variant
+    pub lifetime: Ident,
+    /// the Default implementation is used if None
+    pub factory: Option<Path>,
+    /// the struct implements Service itself, e.g. for its lifecycle hooks
+    pub hooks: bool,
+}
+
+impl Parse for ServiceArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut provides = None;
+        let mut lifetime = Ident::new("Scoped", input.span());
+        let mut factory = None;
+        let mut hooks = false;
+        while !input.is_empty() {
+            let ident: Ident = input.parse()?;
+            if ident == "hooks" {
+                hooks = true;
+                if input.is_empty() {
+                    break;
+                }
+                input.parse::<Token![,]>()?;
+                continue;
+            }
+            input.parse::<Token![=]>()?;
+            match ident.to_string().as_str() {
+                "provides" => provides = Some(input.parse::<Type>()?),
+                "lifetime" => {
+                    let value: Ident = input.parse()?;
+                    lifetime = match value.to_string().as_str() {
+                        "singleton" => Ident::new("Singleton", value.span()),
+                        "scoped" => Ident::new("Scoped", value.span()),
+                        "child_scoped" => Ident::new("ChildScoped", value.span()),
+                        "transient" => Ident::new("Transient", value.span()),
+                        _ => {
+                            return Err(syn::Error::new(
+                                value.span(),
+                                "lifetime must be singleton, scoped, child_scoped or transient",
+                            ))
+                        }
+                    };
+                }
+                "factory" => factory = Some(input.parse::<Path>()?),
+                _ => {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        format!("unknown service option {}", ident),
+                    ))
+                }
+            }
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        let provides = provides
+            .ok_or_else(|| syn::Error::new(input.span(), "missing provides = dyn Trait"))?;
+        Ok(ServiceArgs {
+            provides,
+            lifetime,
+            factory,
+            hooks,
+        })
+    }
+}
+
+/// Trait of a `dyn Trait` type, as the Castable derive expects it
+fn provided_trait(provides: &Type) -> Result<&Path> {
+    let bound = match provides {
+        Type::TraitObject(object) => object.bounds.first(),
+        _ => None,
     };
+    match bound {
+        Some(TypeParamBound::Trait(bound)) => Ok(&bound.path),
+        _ => Err(syn::Error::new_spanned(
+            provides,
+            "provides must be a trait object like dyn Trait",
+        )),
+    }
+}
 
-    //println!("{}", output.to_string().as_str());
+/// Declares the struct as implementation of a service. It derives Castable
+/// and Service for the struct and registers it in the container on
+/// Registry::register_all(). The struct is built with Default unless a
+/// factory is given. Without lifecycle hooks the service is
+/// Service::shareable(). With the hooks option Service is not derived, so
+/// the struct can implement initialize and uninitialize itself.
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args: ServiceArgs = parse_macro_input!(attr);
+    let mut service = parse_macro_input!(item as ItemStruct);
+
+    let provided = match provided_trait(&args.provides) {
+        Ok(provided) => provided.clone(),
+        Err(err) => return err.to_compile_error().into(),
+    };
+    if !service.generics.params.is_empty() {
+        return syn::Error::new_spanned(
+            &service.generics,
+            "[service]: generic services are not supported",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    // after the own attributes of the struct, so the derive does not see
+    // derive attributes it cannot handle
+    service
+        .attrs
+        .push(syn::parse_quote!(#[derive(::traitcast_derive::Castable)]));
+    service.attrs.push(syn::parse_quote!(#[Traits(#provided)]));
+
+    let name = &service.ident;
+    let provides = &args.provides;
+    let lifetime = &args.lifetime;
+    let factory = match &args.factory {
+        Some(factory) => quote!(#factory),
+        None => quote!(|_: &::di::registry::Resolver| {
+            ::std::result::Result::Ok(::std::boxed::Box::new(<#name as ::std::default::Default>::default())
+                as ::std::boxed::Box<dyn ::traitcast::Castable + ::std::marker::Sync>)
+        }),
+    };
+
+    let service_impl = if args.hooks {
+        quote!()
+    } else {
+        quote! {
+            impl ::di::service::Service for #name {
+                fn shareable(&self) -> bool {
+                    true
+                }
+            }
+        }
+    };
+
+    let output = quote! {
+        #service
+
+        #service_impl
+
+        const _: () = {
+            fn register(
+                registry: &::di::registry::Registry,
+            ) -> ::std::result::Result<(), ::error::Error<::di::registry::ErrorCode>> {
+                registry.register_service::<#provides>(
+                    #factory,
+                    ::di::service::Lifetime::#lifetime,
+                )
+            }
+            ::di::inventory::submit! {
+                ::di::registry::Registration::new(register)
+            }
+        };
+    };
     output.into()
-    //quote!().into()
 }
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use di::registry::{ErrorCode, Registry, Resolver, SimpleSession};
+use di::service::{Lifetime, Service};
+use error::Error;
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+use di_derive::inject;
+
+trait CounterService: Service {
+    fn count(&self) -> u32;
+    fn increment(&mut self) -> u32;
+}
+#[derive(Castable)]
+#[Traits(CounterService)]
+struct CounterServiceImpl {
+    counter: u32,
+}
+impl CounterServiceImpl {
+    pub fn factory(
+        _: &Resolver,
+    ) -> std::result::Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(CounterServiceImpl { counter: 0 }))
+    }
+}
+impl CounterService for CounterServiceImpl {
+    fn count(&self) -> u32 {
+        self.counter
+    }
+
+    fn increment(&mut self) -> u32 {
+        self.counter += 1;
+        self.counter
+    }
+}
+impl Service for CounterServiceImpl {
+    fn shareable(&self) -> bool {
+        true
+    }
+}
+
+fn registry() -> Registry {
+    let registry = Registry::default();
+    registry
+        .register_service::<dyn CounterService>(CounterServiceImpl::factory, Lifetime::Singleton)
+        .unwrap();
+    registry
+}
+
+#[inject(registry = registry, counter)]
+async fn count(registry: &Registry, counter: &dyn CounterService) -> u32 {
+    counter.count()
+}
+
+#[inject(registry = registry, counter)]
+async fn shared_counter(registry: &Registry, counter: Arc<dyn CounterService>) -> u32 {
+    tokio::task::yield_now().await;
+    counter.count()
+}
+
+#[inject(registry = registry, counter)]
+async fn slow_increment(
+    registry: &Registry,
+    #[session] session: &dyn Session,
+    counter: &mut dyn CounterService,
+) -> u32 {
+    // other tasks run while the service is locked
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    counter.increment()
+}
+
+#[tokio::test]
+async fn inject_in_async_function() {
+    let registry = registry();
+    let session = SimpleSession::new();
+
+    assert_eq!(count(&registry).await.unwrap(), 0);
+    assert_eq!(slow_increment(&registry, &session).await.unwrap(), 1);
+    assert_eq!(count(&registry).await.unwrap(), 1);
+    assert_eq!(shared_counter(&registry).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn wait_for_a_service_without_blocking_the_runtime() {
+    let registry = registry();
+
+    // both tasks run on the single runtime thread, the second one waits for
+    // the service held by the first one across an await
+    let first = tokio::spawn({
+        let registry = registry.clone();
+        async move { slow_increment(&registry, &SimpleSession::new()).await }
+    });
+    let second = tokio::spawn({
+        let registry = registry.clone();
+        async move { slow_increment(&registry, &SimpleSession::new()).await }
+    });
+
+    let mut results = vec![
+        first.await.unwrap().unwrap(),
+        second.await.unwrap().unwrap(),
+    ];
+    results.sort();
+    assert_eq!(results, vec![1, 2]);
+    assert_eq!(count(&registry).await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn read_a_service_in_async_code() {
+    let registry = registry();
+    let session = SimpleSession::new();
+    let counter = registry
+        .get_service::<dyn CounterService>(&session)
+        .unwrap();
+
+    counter.write_async().await.unwrap().increment();
+    let first = counter.read_async().await.unwrap();
+    let second = counter.read_async().await.unwrap();
+    assert_eq!(first.count() + second.count(), 2);
+}
@@ -1,5 +1,5 @@
-use di::registry::{ErrorCode, Registry, SimpleSession};
-use di::service::Service;
+use di::registry::{ErrorCode, Registry, Resolver, SimpleSession};
+use di::service::{Lifetime, Service, ServiceHandle, ServiceMut, ServiceRef};
 use error::Error;
 use proc_macro2::Ident;
 use proc_macro2::Span;
@@ -7,6 +7,7 @@ use proc_macro2::TokenStream;
 use quote::ToTokens;
 use std::collections::HashSet;
 use std::str::FromStr;
+use std::sync::Arc;
 use syn::parse::Parse;
 use syn::parse::ParseStream;
 use syn::punctuated::Punctuated;
@@ -35,8 +36,10 @@ struct SimpleServiceImpl {
     counter: u32,
 }
 impl SimpleServiceImpl {
-    pub fn factory() -> Box<dyn Castable> {
-        Box::new(SimpleServiceImpl { counter: 1 })
+    pub fn factory(
+        _: &Resolver,
+    ) -> std::result::Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(SimpleServiceImpl { counter: 1 }))
     }
 }
 impl SimpleService for SimpleServiceImpl {
@@ -56,8 +59,10 @@ impl Service for SimpleServiceImpl {}
 #[Traits(SampleService)]
 struct SampleServiceImpl {}
 impl SampleServiceImpl {
-    pub fn factory() -> Box<dyn Castable> {
-        Box::new(SampleServiceImpl {})
+    pub fn factory(
+        _: &Resolver,
+    ) -> std::result::Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(SampleServiceImpl {}))
     }
 }
 impl SampleService for SampleServiceImpl {
@@ -65,7 +70,31 @@ impl SampleService for SampleServiceImpl {
         100
     }
 }
-impl Service for SampleServiceImpl {}
+impl Service for SampleServiceImpl {
+    fn shareable(&self) -> bool {
+        true
+    }
+}
+
+trait GlobalService: Service {
+    fn foo(&self) -> u32;
+}
+#[derive(Castable)]
+#[Traits(GlobalService)]
+struct GlobalServiceImpl {}
+impl GlobalServiceImpl {
+    pub fn factory(
+        _: &Resolver,
+    ) -> std::result::Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(GlobalServiceImpl {}))
+    }
+}
+impl GlobalService for GlobalServiceImpl {
+    fn foo(&self) -> u32 {
+        10
+    }
+}
+impl Service for GlobalServiceImpl {}
 
 #[inject(injected_param)]
 fn func_ref(explicit_param: u32, injected_param: &dyn SimpleService) -> u32 {
@@ -109,6 +138,59 @@ fn func_generic<T>(explicit_param: T, injected_param: &mut dyn SimpleService) ->
     explicit_param
 }
 
+#[inject(registry = registry, injected_param)]
+fn func_container(registry: &Registry, injected_param: &mut dyn SimpleService) -> u32 {
+    injected_param.bar()
+}
+#[inject(registry = Registry::global(), injected_param)]
+fn func_global(injected_param: &dyn GlobalService) -> u32 {
+    injected_param.foo()
+}
+
+#[inject(registry = registry, injected_param)]
+fn func_where<T>(registry: &Registry, explicit_param: T, injected_param: &dyn SimpleService) -> T
+where
+    T: std::ops::Add<u32, Output = T>,
+{
+    explicit_param + injected_param.foo()
+}
+#[inject(registry = registry, reader, writer, handle)]
+fn func_guards(
+    registry: &Registry,
+    reader: ServiceRef<dyn SampleService>,
+    mut writer: ServiceMut<dyn SimpleService>,
+    handle: ServiceHandle<dyn SampleService>,
+) -> u32 {
+    let first = reader.foo() + writer.bar();
+    drop(reader);
+    first + handle.read().unwrap().foo()
+}
+
+#[inject(registry = registry, shared)]
+fn func_shared(registry: &Registry, shared: Arc<dyn SampleService>) -> Arc<dyn SampleService> {
+    shared
+}
+#[inject(registry = registry, injected_param)]
+unsafe extern "Rust" fn func_abi(registry: &Registry, injected_param: &dyn SampleService) -> u32 {
+    injected_param.foo()
+}
+
+struct Counter {
+    registry: Registry,
+    calls: u32,
+}
+impl Counter {
+    #[inject(registry = &self.registry, injected_param)]
+    fn count(&self, injected_param: &dyn SampleService) -> u32 {
+        injected_param.foo() + self.calls
+    }
+    #[inject(registry = &self.registry, injected_param)]
+    pub fn count_mut(&mut self, injected_param: &mut dyn SimpleService) -> u32 {
+        self.calls += 1;
+        injected_param.bar() + self.calls
+    }
+}
+
 struct Args {
     pub vars: HashSet<Ident>,
 }
@@ -199,50 +281,179 @@ fn injects_non_existing_service_as_reference() {
 }
 #[test]
 fn injects_existing_service_as_reference() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).unwrap();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
     assert_eq!(func_ref(1), Ok(1));
-    Registry::unregister_service::<dyn SimpleService>().unwrap();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .unwrap();
 }
 #[test]
 
 fn injects_existing_service_as_reference_no_params() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).unwrap();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
     assert_eq!(func_no_params(), Ok(0));
-    Registry::unregister_service::<dyn SimpleService>().unwrap();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .unwrap();
 }
 #[test]
 fn injects_two_existing_service_as_reference() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).unwrap();
-    Registry::register_service::<dyn SampleService>(SampleServiceImpl::factory).unwrap();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    Registry::global()
+        .register_service::<dyn SampleService>(SampleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
     assert_eq!(func_ref_with_two(1), Ok(101));
-    Registry::unregister_service::<dyn SimpleService>().unwrap();
-    Registry::unregister_service::<dyn SampleService>().unwrap();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .unwrap();
+    Registry::global()
+        .unregister_service::<dyn SampleService>()
+        .unwrap();
 }
 #[test]
 fn injects_existing_service_as_mutable() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).unwrap();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
     assert_eq!(func_mut(1), Ok(2));
     assert_eq!(func_mut(1), Ok(3));
-    Registry::unregister_service::<dyn SimpleService>().unwrap();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .unwrap();
 }
 #[test]
 fn injects_two_existing_service_as_mutable() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).unwrap();
-    Registry::register_service::<dyn SampleService>(SampleServiceImpl::factory).unwrap();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    Registry::global()
+        .register_service::<dyn SampleService>(SampleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
     assert_eq!(func_mut_with_two(1), Ok(102));
-    Registry::unregister_service::<dyn SimpleService>().unwrap();
-    Registry::unregister_service::<dyn SampleService>().unwrap();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .unwrap();
+    Registry::global()
+        .unregister_service::<dyn SampleService>()
+        .unwrap();
 }
 #[test]
 fn injects_existing_service_as_reference_with_session() {
     let session = SimpleSession::new();
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).unwrap();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
     assert_eq!(func_session(1, &session), Ok(1));
-    Registry::unregister_service::<dyn SimpleService>().unwrap();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .unwrap();
 }
 #[test]
 fn injects_existing_service_as_generic() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).unwrap();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
     assert_eq!(func_generic(1), Ok(1));
-    Registry::unregister_service::<dyn SimpleService>().unwrap();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .unwrap();
+}
+#[test]
+fn injects_service_of_container() {
+    let first = Registry::default();
+    let second = Registry::default();
+    first
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(func_container(&first), Ok(1));
+    assert_eq!(func_container(&first), Ok(2));
+    assert_eq!(
+        func_container(&second),
+        Err(Error::new(ErrorCode::UnregisteredService, ""))
+    );
+    second
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(func_container(&second), Ok(1));
+}
+#[test]
+fn injects_service_of_global_container() {
+    assert_eq!(
+        func_global(),
+        Err(Error::new(ErrorCode::UnregisteredService, ""))
+    );
+    Registry::global()
+        .register_service::<dyn GlobalService>(GlobalServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(func_global(), Ok(10));
+    Registry::global()
+        .unregister_service::<dyn GlobalService>()
+        .unwrap();
+}
+#[test]
+fn injects_service_with_where_clause() {
+    let registry = Registry::default();
+    registry
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(func_where(&registry, 1u32), Ok(1));
+}
+#[test]
+fn injects_service_guards_and_handles() {
+    let registry = Registry::default();
+    registry
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    registry
+        .register_service::<dyn SampleService>(SampleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(func_guards(&registry), Ok(201));
+    assert_eq!(func_guards(&registry), Ok(202));
+}
+#[test]
+fn injects_service_in_methods() {
+    let registry = Registry::default();
+    registry
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    registry
+        .register_service::<dyn SampleService>(SampleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let mut counter = Counter { registry, calls: 0 };
+    assert_eq!(counter.count(), Ok(100));
+    assert_eq!(counter.count_mut(), Ok(2));
+    assert_eq!(counter.count(), Ok(101));
+}
+#[test]
+fn injects_shared_service() {
+    let registry = Registry::default();
+    registry
+        .register_service::<dyn SampleService>(SampleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let shared = func_shared(&registry).unwrap();
+    assert_eq!(shared.foo(), 100);
+
+    let session = SimpleSession::default();
+    let handle = registry.get_service::<dyn SampleService>(&session).unwrap();
+    assert!(handle.read().is_ok());
+    assert_eq!(
+        handle.write().err(),
+        Some(Error::new(ErrorCode::ServiceError, ""))
+    );
+    drop(shared);
+    assert!(handle.write().is_ok());
+}
+#[test]
+fn injects_service_in_function_with_abi() {
+    let registry = Registry::default();
+    registry
+        .register_service::<dyn SampleService>(SampleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(unsafe { func_abi(&registry) }, Ok(100));
 }
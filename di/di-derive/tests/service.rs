@@ -0,0 +1,153 @@
+use di::registry::{ErrorCode, Registry, Resolver, SimpleSession};
+use di::service::{Service, ServiceHandle};
+use error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use traitcast::Castable;
+
+use di_derive::service;
+
+trait GreetingService: Service {
+    fn greet(&self) -> String;
+}
+trait CounterService: Service {
+    fn next(&mut self) -> u32;
+}
+
+#[service(provides = dyn GreetingService, lifetime = singleton)]
+#[derive(Default)]
+struct GreetingServiceImpl {}
+impl GreetingService for GreetingServiceImpl {
+    fn greet(&self) -> String {
+        String::from("hello")
+    }
+}
+
+#[service(
+    provides = dyn CounterService,
+    lifetime = transient,
+    factory = CounterServiceImpl::factory
+)]
+struct CounterServiceImpl {
+    greeting: ServiceHandle<dyn GreetingService>,
+    counter: u32,
+}
+impl CounterServiceImpl {
+    fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(CounterServiceImpl {
+            greeting: resolver.get_service::<dyn GreetingService>()?,
+            counter: 10,
+        }))
+    }
+}
+impl CounterService for CounterServiceImpl {
+    fn next(&mut self) -> u32 {
+        assert_eq!(self.greeting.read().unwrap().greet(), "hello");
+        self.counter += 1;
+        self.counter
+    }
+}
+
+mod farewell {
+    use di::service::Service;
+
+    pub trait FarewellService: Service {
+        fn farewell(&self) -> String;
+    }
+}
+
+#[service(provides = dyn farewell::FarewellService)]
+#[derive(Default)]
+struct FarewellServiceImpl {}
+impl farewell::FarewellService for FarewellServiceImpl {
+    fn farewell(&self) -> String {
+        String::from("bye")
+    }
+}
+
+trait ConnectionService: Service {
+    fn is_open(&self) -> bool;
+}
+
+static CONNECTION_CLOSED: AtomicBool = AtomicBool::new(false);
+
+#[service(provides = dyn ConnectionService, hooks)]
+#[derive(Default)]
+struct ConnectionServiceImpl {
+    open: bool,
+}
+impl ConnectionService for ConnectionServiceImpl {
+    fn is_open(&self) -> bool {
+        self.open
+    }
+}
+impl Service for ConnectionServiceImpl {
+    fn initialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        self.open = true;
+        Ok(())
+    }
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        self.open = false;
+        CONNECTION_CLOSED.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn next_of(container: &Registry) -> u32 {
+    let service = container
+        .get_service::<dyn CounterService>(&SimpleSession::default())
+        .unwrap();
+    let mut service = service.write().unwrap();
+    service.next()
+}
+
+#[test]
+fn registers_declared_services() {
+    let container = Registry::default();
+    assert_eq!(container.register_all(), Ok(()));
+
+    let service = container
+        .get_service::<dyn GreetingService>(&SimpleSession::new())
+        .unwrap();
+    assert_eq!(service.read().unwrap().greet(), "hello");
+}
+#[test]
+fn uses_lifetime_and_factory_of_declaration() {
+    let container = Registry::default();
+    container.register_all().unwrap();
+
+    assert_eq!(next_of(&container), 11);
+    assert_eq!(next_of(&container), 11);
+}
+#[test]
+fn registers_declared_services_once() {
+    let container = Registry::default();
+    container.register_all().unwrap();
+    assert_eq!(
+        container.register_all(),
+        Err(Error::new(ErrorCode::AlreadyRegisteredService, ""))
+    );
+}
+#[test]
+fn registers_services_provided_by_path() {
+    let container = Registry::default();
+    container.register_all().unwrap();
+
+    let service = container
+        .get_service::<dyn farewell::FarewellService>(&SimpleSession::new())
+        .unwrap();
+    assert_eq!(service.read().unwrap().farewell(), "bye");
+}
+#[test]
+fn calls_hooks_of_services_implementing_service() {
+    let container = Registry::default();
+    container.register_all().unwrap();
+    let session = SimpleSession::new();
+
+    let service = container
+        .get_service::<dyn ConnectionService>(&session)
+        .unwrap();
+    assert!(service.read().unwrap().is_open());
+    drop(service);
+    container.clear_session(&session).unwrap();
+    assert!(CONNECTION_CLOSED.load(Ordering::SeqCst));
+}
@@ -0,0 +1,6 @@
+use di_derive::inject;
+
+#[inject(storage)]
+struct Store {}
+
+fn main() {}
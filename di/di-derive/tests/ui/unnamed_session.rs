@@ -0,0 +1,8 @@
+use di_derive::inject;
+
+trait Storage {}
+
+#[inject(storage)]
+fn store(#[session] _: &dyn Session, storage: &dyn Storage) {}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use di_derive::inject;
+
+trait Storage {}
+
+#[inject(storage)]
+fn store(storage: Box<dyn Storage>) {}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use di_derive::inject;
+
+trait Storage {}
+
+#[inject(storage, cache)]
+fn store(storage: &dyn Storage) {}
+
+fn main() {}
@@ -0,0 +1,258 @@
+use error::Error;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use traitcast::Castable;
+
+use crate::registry::{ErrorCode, Prototype, Registry, Resolver};
+use crate::service::{Lifetime, Service, ServiceFactory};
+
+/// Builds a service with the parameters of its configuration
+pub type ConfiguredFactory<P> =
+    fn(&Resolver, &P) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>>;
+
+/// Registry::bind for the service type the implementation was added for
+type Bind = fn(&Registry, Option<&str>, Prototype, Lifetime) -> Result<(), Error<ErrorCode>>;
+/// Registry::unbind for the service type the implementation was added for
+type Unbind = fn(&Registry, Option<&str>) -> Result<(), Error<ErrorCode>>;
+/// Prototype of an implementation with the given parameters
+type Instantiate = Box<dyn Fn(Value) -> Result<Prototype, String> + Send + Sync>;
+
+///////////////////////////////////////////////////////////////////////////////
+/// Implementations a Configuration chooses from, by service type and name
+#[derive(Default)]
+pub struct Implementations {
+    /// by type name of the service trait
+    services: BTreeMap<String, ServiceImplementations>,
+}
+
+struct ServiceImplementations {
+    bind: Bind,
+    unbind: Unbind,
+    factories: BTreeMap<String, Instantiate>,
+}
+
+impl Implementations {
+    /// Adds an implementation of Impl without parameters
+    pub fn add<Impl: Service + ?Sized>(
+        &mut self,
+        name: &str,
+        factory: ServiceFactory,
+    ) -> &mut Self {
+        self.insert::<Impl>(
+            name,
+            Box::new(move |parameters| match parameters {
+                Value::Object(parameters) if parameters.is_empty() => {
+                    Ok(Prototype::Factory(factory))
+                }
+                _ => Err("the implementation takes no parameters".to_owned()),
+            }),
+        )
+    }
+
+    /// Adds an implementation of Impl built with parameters of type P. The
+    /// parameters are read when the configuration is bound.
+    pub fn add_configured<Impl, P>(
+        &mut self,
+        name: &str,
+        factory: ConfiguredFactory<P>,
+    ) -> &mut Self
+    where
+        Impl: Service + ?Sized,
+        P: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.insert::<Impl>(
+            name,
+            Box::new(move |parameters| {
+                let parameters = serde_json::from_value::<P>(parameters)
+                    .map_err(|err| format!("invalid parameters: {}", err))?;
+                Ok(Prototype::Configured(Arc::new(
+                    move |resolver: &Resolver| factory(resolver, &parameters),
+                )))
+            }),
+        )
+    }
+
+    fn insert<Impl: Service + ?Sized>(
+        &mut self,
+        name: &str,
+        instantiate: Instantiate,
+    ) -> &mut Self {
+        self.services
+            .entry(std::any::type_name::<Impl>().to_owned())
+            .or_insert_with(|| ServiceImplementations {
+                bind: Registry::bind::<Impl>,
+                unbind: Registry::unbind::<Impl>,
+                factories: BTreeMap::new(),
+            })
+            .factories
+            .insert(name.to_owned(), instantiate);
+        self
+    }
+
+    /// Implementations of the service with the given type name, which may
+    /// leave out the module paths as long as only one service matches
+    fn service(&self, name: &str) -> Result<&ServiceImplementations, String> {
+        if let Some(implementations) = self.services.get(name) {
+            return Ok(implementations);
+        }
+        let matches: Vec<(&String, &ServiceImplementations)> = self
+            .services
+            .iter()
+            .filter(|(service, _)| short_name(service) == name)
+            .collect();
+        match matches.as_slice() {
+            [] => Err("unknown service".to_owned()),
+            [(_, implementations)] => Ok(implementations),
+            _ => Err(format!(
+                "ambiguous service, matches {}",
+                matches
+                    .iter()
+                    .map(|(service, _)| service.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Implementations chosen for the services, read from TOML or JSON and bound
+/// by Registry::configure(). Every binding key, the service type followed by
+/// #name for named bindings, maps to the name of the implementation, its
+/// lifetime (scoped by default) and its parameters:
+///
+/// ```toml
+/// ["dyn EventBusService"]
+/// implementation = "queued"
+/// lifetime = "singleton"
+/// parameters = { capacity = 64 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Configuration {
+    services: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceConfiguration {
+    implementation: String,
+    #[serde(default = "scoped")]
+    lifetime: Lifetime,
+    #[serde(default = "no_parameters")]
+    parameters: Value,
+}
+
+fn scoped() -> Lifetime {
+    Lifetime::Scoped
+}
+
+fn no_parameters() -> Value {
+    Value::Object(Map::new())
+}
+
+/// Service of a Configuration, ready to be bound
+pub(crate) struct Binding {
+    pub(crate) bind: Bind,
+    pub(crate) unbind: Unbind,
+    pub(crate) name: Option<String>,
+    pub(crate) prototype: Prototype,
+    pub(crate) lifetime: Lifetime,
+}
+
+impl Configuration {
+    pub fn from_toml(text: &str) -> Result<Configuration, Error<ErrorCode>> {
+        toml::from_str::<Value>(text)
+            .map_err(|err| {
+                let message = match err.message() {
+                    "" => "invalid syntax".to_owned(),
+                    message => message.lines().collect::<Vec<&str>>().join(", "),
+                };
+                match err.span() {
+                    Some(span) => {
+                        let line = text[..span.start].matches('\n').count() + 1;
+                        invalid(&format!("line {}: {}", line, message))
+                    }
+                    None => invalid(&message),
+                }
+            })
+            .and_then(Configuration::from_value)
+    }
+
+    pub fn from_json(text: &str) -> Result<Configuration, Error<ErrorCode>> {
+        serde_json::from_str::<Value>(text)
+            .map_err(|err| invalid(&err.to_string()))
+            .and_then(Configuration::from_value)
+    }
+
+    pub fn from_value(value: Value) -> Result<Configuration, Error<ErrorCode>> {
+        match value {
+            Value::Object(services) => Ok(Configuration { services }),
+            _ => Err(invalid("expected a table of services")),
+        }
+    }
+
+    /// Checks every service of the configuration and builds its prototype
+    pub(crate) fn bindings(
+        &self,
+        implementations: &Implementations,
+    ) -> Result<Vec<Binding>, Error<ErrorCode>> {
+        self.services
+            .iter()
+            .map(|(key, service)| {
+                Configuration::binding(implementations, key, service).map_err(|message| {
+                    Error::new(
+                        ErrorCode::InvalidConfiguration,
+                        format!("Invalid configuration of {} ({})", key, message).as_str(),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn binding(
+        implementations: &Implementations,
+        key: &str,
+        service: &Value,
+    ) -> Result<Binding, String> {
+        let configuration =
+            ServiceConfiguration::deserialize(service).map_err(|err| err.to_string())?;
+        let (service, name) = match key.split_once('#') {
+            Some((service, name)) => (service, Some(name.to_owned())),
+            None => (key, None),
+        };
+        let service = implementations.service(service)?;
+        let instantiate = service
+            .factories
+            .get(&configuration.implementation)
+            .ok_or_else(|| format!("unknown implementation {}", configuration.implementation))?;
+
+        Ok(Binding {
+            bind: service.bind,
+            unbind: service.unbind,
+            name,
+            prototype: instantiate(configuration.parameters)?,
+            lifetime: configuration.lifetime,
+        })
+    }
+}
+
+fn invalid(message: &str) -> Error<ErrorCode> {
+    Error::new(
+        ErrorCode::InvalidConfiguration,
+        format!("Invalid configuration ({})", message).as_str(),
+    )
+}
+
+/// Type name without module paths, dyn a::B<c::D> becomes dyn B<D>
+fn short_name(type_name: &str) -> String {
+    let mut segments: Vec<&str> = type_name.split("::").collect();
+    let last = segments.pop().unwrap_or_default();
+    segments
+        .into_iter()
+        .map(|segment| segment.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_'))
+        .chain(std::iter::once(last))
+        .collect()
+}
@@ -0,0 +1,105 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::service::Lifetime;
+
+///////////////////////////////////////////////////////////////////////////////
+/// State of a container, see Registry::introspect()
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContainerInfo {
+    /// registered services, ordered by service type and registration
+    pub registrations: Vec<RegistrationInfo>,
+    /// binding keys of the instantiated singletons
+    pub singletons: Vec<String>,
+    /// sessions holding instances
+    pub sessions: Vec<SessionInfo>,
+    /// services resolved by the factories of other services
+    pub dependencies: Vec<DependencyInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegistrationInfo {
+    /// binding key, the type name followed by #name for named bindings
+    pub key: String,
+    /// type name of the service trait
+    pub service: String,
+    pub name: Option<String>,
+    pub lifetime: Lifetime,
+    /// true while Registry::override_service() replaces the factory
+    pub overridden: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub key: u32,
+    /// binding keys of the instances kept for the session
+    pub services: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DependencyInfo {
+    /// binding key of the service being built
+    pub service: String,
+    /// binding key of the service its factory resolved
+    pub dependency: String,
+}
+
+impl ContainerInfo {
+    /// Dependency graph in Graphviz DOT. Services that are not registered are
+    /// drawn dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph di {\n");
+        let registered: BTreeSet<&str> = self
+            .registrations
+            .iter()
+            .map(|registration| registration.key.as_str())
+            .collect();
+
+        for registration in self.registrations.iter() {
+            let _ = writeln!(
+                dot,
+                "    {} [label={}];",
+                quoted(&registration.key),
+                quoted(&format!(
+                    "{}\n{}",
+                    registration.key, registration.lifetime
+                ))
+            );
+        }
+        let unregistered: BTreeSet<&str> = self
+            .dependencies
+            .iter()
+            .map(|dependency| dependency.dependency.as_str())
+            .filter(|dependency| !registered.contains(dependency))
+            .collect();
+        for dependency in unregistered {
+            let _ = writeln!(dot, "    {} [style=dashed];", quoted(dependency));
+        }
+        for dependency in self.dependencies.iter() {
+            let _ = writeln!(
+                dot,
+                "    {} -> {};",
+                quoted(&dependency.service),
+                quoted(&dependency.dependency)
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serializing plain data cannot fail")
+    }
+}
+
+/// Quoted DOT identifier
+fn quoted(id: &str) -> String {
+    format!(
+        "\"{}\"",
+        id.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
@@ -1,2 +1,7 @@
+pub mod config;
+pub mod introspection;
 pub mod registry;
 pub mod service;
+
+#[doc(hidden)]
+pub use inventory;
@@ -1,12 +1,20 @@
 use error::Error;
 use log::error;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use traitcast::Castable;
 
-use crate::service::{Service, ServiceFactory};
+use crate::config::{Configuration, Implementations};
+use crate::introspection::{ContainerInfo, DependencyInfo, RegistrationInfo, SessionInfo};
+use crate::service::{
+    Instance, InstanceMut, Lifetime, Service, ServiceFactory, ServiceHandle, SharedService,
+};
+
+/// Locks an instance to uninitialize it, see SharedService::write_blocking()
+type Lock = fn(&SharedService) -> Result<InstanceMut<'_>, Error<ErrorCode>>;
 
 #[derive(PartialEq, Debug)]
 pub enum ErrorCode {
@@ -18,10 +26,29 @@ pub enum ErrorCode {
     CouldNotUninitialize,
     Unimplemented,
     ServiceError,
+    DependencyCycle,
+    CouldNotInitialize,
+    InvalidConfiguration,
+    LifetimeMismatch,
 }
 
-pub trait Session {
+/// Sessions are shared with async tasks, which may move between threads.
+/// A session nested in another one, such as a request of a user session,
+/// returns its parent.
+pub trait Session: Send + Sync {
     fn key(&self) -> u32;
+
+    fn parent(&self) -> Option<&dyn Session> {
+        None
+    }
+}
+
+/// Outermost session of a session
+fn root(session: &dyn Session) -> &dyn Session {
+    match session.parent() {
+        Some(parent) => root(parent),
+        None => session,
+    }
 }
 pub struct SimpleSession {
     key: u32,
@@ -45,182 +72,939 @@ impl Session for SimpleSession {
 
 static SESSION_COUNTER: AtomicU32 = AtomicU32::new(1);
 
+///////////////////////////////////////////////////////////////////////////////
+/// Session that clears its services when it is dropped, see
+/// Registry::scope(). Child scopes share the scoped services of their parent
+/// and own the child scoped ones. A scope stays alive as long as one of its
+/// clones or children does.
+#[derive(Clone)]
+pub struct Scope {
+    inner: Arc<ScopeInner>,
+}
+struct ScopeInner {
+    registry: Registry,
+    key: u32,
+    parent: Option<Arc<ScopeInner>>,
+}
+
+impl Scope {
+    /// Nested scope, for example for a request of a user session
+    pub fn child(&self) -> Scope {
+        Scope {
+            inner: Arc::new(ScopeInner {
+                registry: self.inner.registry.clone(),
+                key: SESSION_COUNTER.fetch_add(1, Ordering::Relaxed),
+                parent: Some(self.inner.clone()),
+            }),
+        }
+    }
+}
+
+impl Session for Scope {
+    fn key(&self) -> u32 {
+        self.inner.key()
+    }
+
+    fn parent(&self) -> Option<&dyn Session> {
+        self.inner.parent()
+    }
+}
+
+impl Session for ScopeInner {
+    fn key(&self) -> u32 {
+        self.key
+    }
+
+    fn parent(&self) -> Option<&dyn Session> {
+        self.parent.as_deref().map(|parent| parent as &dyn Session)
+    }
+}
+
+impl Drop for ScopeInner {
+    fn drop(&mut self) {
+        if let Err(err) = self.registry.clear_scope(self) {
+            error!(target: "di", "{}", err.message);
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// A DI container. Registry::default() creates a new, empty container and
+/// cloning it gives another handle to the same container. The services are
+/// uninitialized when the last handle is dropped. Registry::global() returns
+/// the global container.
+#[derive(Clone)]
 pub struct Registry {
-    registered_service_factories: HashMap<String, ServiceFactory>,
-    available_sessions: HashMap<u32, HashMap<String, Arc<Mutex<Box<dyn Castable>>>>>,
+    services: Arc<Mutex<Services>>,
+    /// notified when a service is no longer being built
+    built: Arc<Condvar>,
+}
+/// Service instances by binding key
+type Instances = HashMap<String, Stored>;
+struct Services {
+    /// factories by binding key, see binding_key()
+    registered_service_factories: HashMap<String, (Prototype, Lifetime)>,
+    /// binding keys of a service type in the order of their registration
+    bindings: HashMap<String, Vec<String>>,
+    /// keys of the factories replacing a binding, the last one is used
+    overrides: HashMap<String, Vec<String>>,
+    /// binding keys resolved by the factory of a binding
+    dependencies: BTreeMap<String, BTreeSet<String>>,
+    singletons: Instances,
+    available_sessions: HashMap<u32, Instances>,
+    /// services being built by a resolver, see Services::slot()
+    building: HashSet<Slot>,
+    next_sequence: u64,
+    next_override: u32,
+}
+/// Instance of a binding: the binding key and the session keeping the
+/// instance, None for singletons
+type Slot = (Option<u32>, String);
+
+/// Builds the instances of a binding
+#[derive(Clone)]
+pub(crate) enum Prototype {
+    Factory(ServiceFactory),
+    /// factory of a configuration, bound to its parameters
+    Configured(ConfiguredPrototype),
+}
+pub(crate) type ConfiguredPrototype =
+    Arc<dyn Fn(&Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> + Send + Sync>;
+
+impl Prototype {
+    fn create(&self, resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        match self {
+            Prototype::Factory(factory) => factory(resolver),
+            Prototype::Configured(factory) => factory(resolver),
+        }
+    }
 }
 
-impl Registry {
-    pub fn default() -> Registry {
-        Registry {
-            registered_service_factories: HashMap::new(),
-            available_sessions: HashMap::new(),
+/// Instance kept by the registry
+struct Stored {
+    /// creation order, a service is created after the services it depends on
+    sequence: u64,
+    instance: Instance,
+    uninitialize: Uninitialize,
+}
+/// Marks an instance as being built until it is dropped, whether the build
+/// succeeded or not
+struct Building<'a> {
+    registry: &'a Registry,
+    slot: Option<Slot>,
+}
+
+impl Drop for Building<'_> {
+    fn drop(&mut self) {
+        if let Some(slot) = &self.slot {
+            let mut services = match self.registry.services.lock() {
+                Ok(services) => services,
+                Err(err) => err.into_inner(),
+            };
+            services.building.remove(slot);
+            self.registry.built.notify_all();
         }
     }
+}
+
+/// Calls Service::uninitialize of the type the instance was created for
+type Uninitialize = fn(&mut (dyn Castable + 'static)) -> Option<Result<(), Error<ErrorCode>>>;
+
+fn uninitialize<Impl: 'static + Service + ?Sized>(
+    service: &mut (dyn Castable + 'static),
+) -> Option<Result<(), Error<ErrorCode>>> {
+    service
+        .query_mut::<Impl>()
+        .map(|service| service.uninitialize())
+}
+
+impl Registry {
+    /// The global container
+    pub fn global() -> &'static Registry {
+        &REGISTRY_INSTANCE
+    }
+
+    /// Registers every service declared with #[service] in this container
+    pub fn register_all(&self) -> Result<(), Error<ErrorCode>> {
+        inventory::iter::<Registration>
+            .into_iter()
+            .try_for_each(|registration| (registration.register)(self))
+    }
 
     pub fn register_service<Impl: Service + ?Sized>(
+        &self,
+        prototype: ServiceFactory,
+        lifetime: Lifetime,
+    ) -> Result<(), Error<ErrorCode>> {
+        self.bind::<Impl>(None, Prototype::Factory(prototype), lifetime)
+    }
+
+    /// Registers another implementation of Impl under the given name
+    pub fn register_named_service<Impl: Service + ?Sized>(
+        &self,
+        name: &str,
+        prototype: ServiceFactory,
+        lifetime: Lifetime,
+    ) -> Result<(), Error<ErrorCode>> {
+        self.bind::<Impl>(Some(name), Prototype::Factory(prototype), lifetime)
+    }
+
+    pub fn unregister_service<Impl: Service + ?Sized>(&self) -> Result<(), Error<ErrorCode>> {
+        self.unbind::<Impl>(None)
+    }
+
+    pub fn unregister_named_service<Impl: Service + ?Sized>(
+        &self,
+        name: &str,
+    ) -> Result<(), Error<ErrorCode>> {
+        self.unbind::<Impl>(Some(name))
+    }
+
+    pub fn clear_session(&self, session: &dyn Session) -> Result<(), Error<ErrorCode>> {
+        let session = self.lock()?.available_sessions.remove(&session.key());
+        match session {
+            Some(services) => Services::uninitialize_all(services.into_iter().collect()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get_service<Impl: 'static + Service + ?Sized>(
+        &self,
+        session: &dyn Session,
+    ) -> Result<ServiceHandle<Impl>, Error<ErrorCode>> {
+        self.resolve::<Impl>(session, binding_key::<Impl>(None), None)
+    }
+
+    pub fn get_named_service<Impl: 'static + Service + ?Sized>(
+        &self,
+        session: &dyn Session,
+        name: &str,
+    ) -> Result<ServiceHandle<Impl>, Error<ErrorCode>> {
+        self.resolve::<Impl>(session, binding_key::<Impl>(Some(name)), None)
+    }
+
+    /// All implementations of Impl, the unnamed and the named ones, in the
+    /// order of their registration
+    pub fn get_services<Impl: 'static + Service + ?Sized>(
+        &self,
+        session: &dyn Session,
+    ) -> Result<Vec<ServiceHandle<Impl>>, Error<ErrorCode>> {
+        self.resolve_all::<Impl>(session, None)
+    }
+
+    /// Builds Impl with the given factory until the returned guard is
+    /// dropped, with the lifetime of the registered service. Instances of the
+    /// replaced service are kept and used again afterwards.
+    pub fn override_service<Impl: Service + ?Sized>(
+        &self,
         prototype: ServiceFactory,
+    ) -> Result<ServiceOverride, Error<ErrorCode>> {
+        let mut registry = self.lock()?;
+
+        let key = binding_key::<Impl>(None);
+        let lifetime = registry
+            .registered_service_factories
+            .get(&key)
+            .map_or(Lifetime::Scoped, |(_, lifetime)| *lifetime);
+
+        registry.next_override += 1;
+        let override_key = format!("{}@override{}", key, registry.next_override);
+        registry.registered_service_factories.insert(
+            override_key.clone(),
+            (Prototype::Factory(prototype), lifetime),
+        );
+        registry
+            .overrides
+            .entry(key.clone())
+            .or_default()
+            .push(override_key.clone());
+
+        Ok(ServiceOverride {
+            registry: self.clone(),
+            key,
+            override_key,
+        })
+    }
+
+    /// New session of this container, cleared when it is dropped. The
+    /// singletons of the container form the application scope around it.
+    pub fn scope(&self) -> Scope {
+        Scope {
+            inner: Arc::new(ScopeInner {
+                registry: self.clone(),
+                key: SESSION_COUNTER.fetch_add(1, Ordering::Relaxed),
+                parent: None,
+            }),
+        }
+    }
+
+    /// Registrations, sessions, instances and dependencies of the container,
+    /// for diagnostics. Dependencies are known once a service was built.
+    pub fn introspect(&self) -> Result<ContainerInfo, Error<ErrorCode>> {
+        let guard = self.lock()?;
+        let registry = &*guard;
+
+        let bindings: BTreeMap<&String, &Vec<String>> = registry.bindings.iter().collect();
+        let registrations = bindings
+            .into_iter()
+            .flat_map(|(service, keys)| {
+                keys.iter().filter_map(move |key| {
+                    let (_, lifetime) = registry.registered_service_factories.get(key)?;
+                    Some(RegistrationInfo {
+                        key: key.clone(),
+                        service: service.clone(),
+                        name: key
+                            .strip_prefix(service.as_str())
+                            .and_then(|name| name.strip_prefix('#'))
+                            .map(str::to_owned),
+                        lifetime: *lifetime,
+                        overridden: registry.overrides.contains_key(key),
+                    })
+                })
+            })
+            .collect();
+
+        let sorted = |instances: &Instances| {
+            let mut keys: Vec<String> = instances.keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let mut sessions: Vec<SessionInfo> = registry
+            .available_sessions
+            .iter()
+            .filter(|(_, instances)| !instances.is_empty())
+            .map(|(key, instances)| SessionInfo {
+                key: *key,
+                services: sorted(instances),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.key);
+
+        let dependencies = registry
+            .dependencies
+            .iter()
+            .flat_map(|(service, dependencies)| {
+                dependencies.iter().map(move |dependency| DependencyInfo {
+                    service: service.clone(),
+                    dependency: dependency.clone(),
+                })
+            })
+            .collect();
+
+        Ok(ContainerInfo {
+            registrations,
+            singletons: sorted(&registry.singletons),
+            sessions,
+            dependencies,
+        })
+    }
+
+    /// Binds the implementations chosen by the configuration. The whole
+    /// configuration is checked before the first service is bound, errors
+    /// name the offending binding key. Binds all services or none, the
+    /// services bound before a binding fails are unbound again.
+    pub fn configure(
+        &self,
+        implementations: &Implementations,
+        configuration: &Configuration,
     ) -> Result<(), Error<ErrorCode>> {
-        let registry = &mut REGISTRY_INSTANCE.lock().or_else(|err| {
-            Err(Error::<ErrorCode>::new(
+        let bindings = configuration.bindings(implementations)?;
+        for (index, binding) in bindings.iter().enumerate() {
+            let name = binding.name.as_deref();
+            if let Err(err) =
+                (binding.bind)(self, name, binding.prototype.clone(), binding.lifetime)
+            {
+                for bound in bindings[..index].iter().rev() {
+                    if let Err(err) = (bound.unbind)(self, bound.name.as_deref()) {
+                        error!(target: "di", "{}", err.message);
+                    }
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Services>, Error<ErrorCode>> {
+        self.services.lock().map_err(|err| {
+            Error::<ErrorCode>::new(
                 ErrorCode::Uninitialized,
                 format!("Di container not initialized: {}", err).as_str(),
-            ))
-        })?;
+            )
+        })
+    }
+
+    /// Waits until a service is no longer being built
+    fn wait<'a>(
+        &self,
+        services: MutexGuard<'a, Services>,
+    ) -> Result<MutexGuard<'a, Services>, Error<ErrorCode>> {
+        self.built.wait(services).map_err(|err| {
+            Error::<ErrorCode>::new(
+                ErrorCode::Uninitialized,
+                format!("Di container not initialized: {}", err).as_str(),
+            )
+        })
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry {
+            services: Arc::new(Mutex::new(Services {
+                registered_service_factories: HashMap::new(),
+                bindings: HashMap::new(),
+                overrides: HashMap::new(),
+                dependencies: BTreeMap::new(),
+                singletons: HashMap::new(),
+                available_sessions: HashMap::new(),
+                building: HashSet::new(),
+                next_sequence: 0,
+                next_override: 0,
+            })),
+            built: Arc::new(Condvar::new()),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Guard of Registry::override_service(). Dropping it uninitializes the
+/// instances built by the override and restores the replaced service.
+pub struct ServiceOverride {
+    registry: Registry,
+    key: String,
+    override_key: String,
+}
+
+impl Drop for ServiceOverride {
+    fn drop(&mut self) {
+        if let Err(err) = self.registry.remove_override(&self.key, &self.override_key) {
+            error!(target: "di", "{}", err.message);
+        }
+    }
+}
+
+/// True if a service with the first lifetime outlives the sessions of a
+/// service with the second one. Transient services are owned by their
+/// dependent, so they never are outlived.
+fn outlives(dependent: Lifetime, dependency: Lifetime) -> bool {
+    let rank = |lifetime| match lifetime {
+        Lifetime::Singleton => Some(0),
+        Lifetime::Scoped => Some(1),
+        Lifetime::ChildScoped => Some(2),
+        Lifetime::Transient => None,
+    };
+    matches!((rank(dependent), rank(dependency)), (Some(dependent), Some(dependency)) if dependent < dependency)
+}
+
+/// Key of the binding of Impl with the given name
+fn binding_key<Impl: ?Sized>(name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{}#{}", std::any::type_name::<Impl>(), name),
+        None => std::any::type_name::<Impl>().to_owned(),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Handed to a ServiceFactory, so the service can get the services it depends
+/// on from the same container and session while it is built
+pub struct Resolver<'a> {
+    registry: &'a Registry,
+    session: &'a dyn Session,
+    /// services being built, outermost first
+    chain: Vec<String>,
+    /// lifetime the dependencies must not be shorter than. A transient
+    /// service has the lifetime of the service it is built for.
+    lifetime: Lifetime,
+    /// services created for the service being built, rolled back if it fails
+    created: RefCell<Vec<Created>>,
+}
+
+/// Service instance created while resolving a service
+struct Created {
+    key: String,
+    lifetime: Lifetime,
+    instance: Instance,
+}
+
+impl Resolver<'_> {
+    pub fn session(&self) -> &dyn Session {
+        self.session
+    }
 
-        let name = std::any::type_name::<Impl>().to_owned();
+    pub fn get_service<Impl: 'static + Service + ?Sized>(
+        &self,
+    ) -> Result<ServiceHandle<Impl>, Error<ErrorCode>> {
+        self.registry
+            .resolve::<Impl>(self.session, binding_key::<Impl>(None), Some(self))
+    }
 
-        if registry.registered_service_factories.contains_key(&name) {
+    pub fn get_named_service<Impl: 'static + Service + ?Sized>(
+        &self,
+        name: &str,
+    ) -> Result<ServiceHandle<Impl>, Error<ErrorCode>> {
+        self.registry
+            .resolve::<Impl>(self.session, binding_key::<Impl>(Some(name)), Some(self))
+    }
+
+    pub fn get_services<Impl: 'static + Service + ?Sized>(
+        &self,
+    ) -> Result<Vec<ServiceHandle<Impl>>, Error<ErrorCode>> {
+        self.registry.resolve_all::<Impl>(self.session, Some(self))
+    }
+}
+
+impl Registry {
+    pub(crate) fn bind<Impl: Service + ?Sized>(
+        &self,
+        name: Option<&str>,
+        prototype: Prototype,
+        lifetime: Lifetime,
+    ) -> Result<(), Error<ErrorCode>> {
+        let registry = &mut self.lock()?;
+
+        let key = binding_key::<Impl>(name);
+
+        if registry.registered_service_factories.contains_key(&key) {
             return Err(Error::new(
                 ErrorCode::AlreadyRegisteredService,
-                format!("Service {} already exists", name).as_str(),
+                format!("Service {} already exists", key).as_str(),
             ));
         }
 
         registry
             .registered_service_factories
-            .insert(name, prototype);
+            .insert(key.clone(), (prototype, lifetime));
+        registry
+            .bindings
+            .entry(std::any::type_name::<Impl>().to_owned())
+            .or_default()
+            .push(key);
 
         Ok(())
     }
 
-    pub fn unregister_service<Impl: Service + ?Sized>() -> Result<(), Error<ErrorCode>> {
-        let registry = &mut REGISTRY_INSTANCE.lock().or_else(|err| {
-            Err(Error::<ErrorCode>::new(
-                ErrorCode::Uninitialized,
-                format!("Di container not initialized: {}", err).as_str(),
-            ))
-        })?;
+    pub(crate) fn unbind<Impl: Service + ?Sized>(
+        &self,
+        name: Option<&str>,
+    ) -> Result<(), Error<ErrorCode>> {
+        let mut registry = self.lock()?;
 
-        let name = std::any::type_name::<Impl>().to_owned();
+        let key = binding_key::<Impl>(name);
 
-        registry.registered_service_factories.remove(&name);
+        registry.registered_service_factories.remove(&key);
+        registry.dependencies.remove(&key);
+        if let Some(keys) = registry.bindings.get_mut(std::any::type_name::<Impl>()) {
+            keys.retain(|bound| *bound != key);
+        }
 
-        registry.available_sessions.iter_mut().for_each(|map| {
-            let service = map.1.remove(&name);
-            if service.is_some() {
-                Registry::unitialize_service(&service.unwrap(), name.as_str());
-            }
-        });
+        let services = registry.take_instances(&key);
+        drop(registry);
 
-        Ok(())
+        Services::uninitialize_all(services)
     }
 
-    pub fn clear_session(session: &dyn Session) -> Result<(), Error<ErrorCode>> {
-        let registry = &mut REGISTRY_INSTANCE.lock().or_else(|err| {
-            Err(Error::<ErrorCode>::new(
-                ErrorCode::Uninitialized,
-                format!("Di container not initialized: {}", err).as_str(),
-            ))
-        })?;
-
-        let session = registry.available_sessions.remove(&session.key());
-        if session.is_some() {
-            registry.uninitialize_services_of_session(session.unwrap());
+    /// Clears the session of a dropped scope. The dropping thread may still
+    /// use one of its services, so services in use are skipped instead of
+    /// waiting for their lock.
+    fn clear_scope(&self, scope: &ScopeInner) -> Result<(), Error<ErrorCode>> {
+        let session = self.lock()?.available_sessions.remove(&scope.key());
+        match session {
+            Some(services) => Services::uninitialize_with(
+                services.into_iter().collect(),
+                SharedService::try_write,
+            ),
+            None => Ok(()),
         }
-        Ok(())
     }
 
-    pub fn get_service<Impl: 'static + Service + ?Sized>(
-        session: &dyn Session,
-    ) -> Result<Arc<Mutex<Box<dyn Castable>>>, Error<ErrorCode>> {
-        let registry = &mut REGISTRY_INSTANCE.lock().or_else(|err| {
-            Err(Error::<ErrorCode>::new(
-                ErrorCode::Uninitialized,
-                format!("Di container not initialized: {}", err).as_str(),
-            ))
-        })?;
+    fn remove_override(&self, key: &str, override_key: &str) -> Result<(), Error<ErrorCode>> {
+        let mut registry = self.lock()?;
 
-        if !registry.available_sessions.contains_key(&session.key()) {
-            registry
-                .available_sessions
-                .insert(session.key(), HashMap::new());
+        registry.registered_service_factories.remove(override_key);
+        if let Some(keys) = registry.overrides.get_mut(key) {
+            keys.retain(|overriding| overriding != override_key);
+            if keys.is_empty() {
+                registry.overrides.remove(key);
+            }
         }
 
-        let name = std::any::type_name::<Impl>().to_string();
+        let services = registry.take_instances(override_key);
+        drop(registry);
 
-        let service_instance = registry.get_session_service::<Impl>(session, &name)?;
+        Services::uninitialize_all(services)
+    }
 
-        Ok(service_instance.clone())
+    fn resolve_all<Impl: 'static + Service + ?Sized>(
+        &self,
+        session: &dyn Session,
+        parent: Option<&Resolver>,
+    ) -> Result<Vec<ServiceHandle<Impl>>, Error<ErrorCode>> {
+        let keys = self
+            .lock()?
+            .bindings
+            .get(std::any::type_name::<Impl>())
+            .cloned()
+            .unwrap_or_default();
+
+        keys.into_iter()
+            .map(|key| self.resolve::<Impl>(session, key, parent))
+            .collect()
     }
-    fn get_session_service<Impl: 'static + Service + ?Sized>(
-        &mut self,
+
+    fn resolve<Impl: 'static + Service + ?Sized>(
+        &self,
         session: &dyn Session,
-        service_name: &String,
-    ) -> Result<&Arc<Mutex<Box<dyn Castable>>>, Error<ErrorCode>> {
-        let session_services =
-            self.available_sessions
-                .get_mut(&session.key())
-                .ok_or_else(|| {
-                    Error::<ErrorCode>::new(
-                        ErrorCode::CouldNotCreateSessionService,
-                        format!(
-                            "Could not create session service for session {}",
-                            session.key()
-                        )
-                        .as_str(),
-                    )
-                })?;
-
-        if !session_services.contains_key(service_name) {
-            let factory = self
-                .registered_service_factories
-                .get(service_name)
-                .ok_or_else(|| {
-                    Error::new(
-                        ErrorCode::UnregisteredService,
-                        format!("Unregistered service {}", service_name).as_str(),
-                    )
-                })?;
-
-            let mut service_instance = factory();
-            match service_instance.as_mut().query_mut::<Impl>() {
-                Some(service) => service.initialize(),
-                None => (),
+        name: String,
+        parent: Option<&Resolver>,
+    ) -> Result<ServiceHandle<Impl>, Error<ErrorCode>> {
+        let chain = parent.map_or(&[][..], |parent| parent.chain.as_slice());
+        if chain.contains(&name) {
+            return Err(Error::new(
+                ErrorCode::DependencyCycle,
+                format!("Dependency cycle {} -> {}", chain.join(" -> "), name).as_str(),
+            ));
+        }
+
+        let (key, factory, lifetime, building) = {
+            let mut registry = self.lock()?;
+
+            if let Some(dependent) = chain.last() {
+                registry
+                    .dependencies
+                    .entry(dependent.clone())
+                    .or_default()
+                    .insert(name.clone());
             }
 
-            session_services.insert(
-                service_name.to_string(),
-                Arc::new(Mutex::new(service_instance)),
-            );
+            // another resolver may be building the instance, wait for it
+            // instead of building a second one
+            loop {
+                let key = registry
+                    .overrides
+                    .get(&name)
+                    .and_then(|keys| keys.last())
+                    .cloned()
+                    .unwrap_or_else(|| name.clone());
+                let (factory, lifetime) = registry
+                    .registered_service_factories
+                    .get(&key)
+                    .cloned()
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorCode::UnregisteredService,
+                            format!("Unregistered service {}", name).as_str(),
+                        )
+                    })?;
+                if let Some(parent) = parent {
+                    // the dependent would keep the instance of the first session
+                    if outlives(parent.lifetime, lifetime) {
+                        return Err(Error::new(
+                            ErrorCode::LifetimeMismatch,
+                            format!(
+                                "Service {} ({}) cannot depend on {} ({})",
+                                chain.join(" -> "),
+                                parent.lifetime,
+                                name,
+                                lifetime
+                            )
+                            .as_str(),
+                        ));
+                    }
+                }
+
+                if let Some(stored) = registry
+                    .instances(lifetime, session)
+                    .and_then(|instances| instances.get(&key))
+                {
+                    return Ok(ServiceHandle::new(stored.instance.clone()));
+                }
+                match Services::slot(&key, lifetime, session) {
+                    Some(slot) if registry.building.contains(&slot) => {
+                        registry = self.wait(registry)?;
+                    }
+                    slot => {
+                        if let Some(slot) = &slot {
+                            registry.building.insert(slot.clone());
+                        }
+                        let building = Building {
+                            registry: self,
+                            slot,
+                        };
+                        break (key, factory, lifetime, building);
+                    }
+                }
+            }
+        };
+
+        // the factory may resolve other services, so the registry must not be
+        // locked while it runs
+        let mut chain = chain.to_vec();
+        chain.push(name.clone());
+        let resolver = Resolver {
+            registry: self,
+            session,
+            chain,
+            lifetime: match (lifetime, parent) {
+                (Lifetime::Transient, Some(parent)) => parent.lifetime,
+                _ => lifetime,
+            },
+            created: RefCell::new(Vec::new()),
+        };
+        let service_instance = Registry::create::<Impl>(&name, &factory, &resolver);
+        let mut created = resolver.created.into_inner();
+        let service_instance: Instance = match service_instance {
+            Ok(service_instance) if lifetime == Lifetime::Transient => {
+                let name = name.clone();
+                SharedService::transient(
+                    service_instance,
+                    Box::new(move |service| {
+                        let uninitialized =
+                            Services::uninitialized(&name, service, uninitialize::<Impl>);
+                        if let Err(err) = uninitialized {
+                            error!(target: "di", "{}", err.message);
+                        }
+                    }),
+                )
+            }
+            Ok(service_instance) => SharedService::new(service_instance),
+            Err(err) => {
+                self.roll_back(session, created);
+                return Err(err);
+            }
+        };
+
+        let stored = match self
+            .lock()?
+            .store::<Impl>(&key, lifetime, session, &service_instance)
+        {
+            Some(stored) => stored,
+            None => service_instance.clone(),
+        };
+        drop(building);
+        if Arc::ptr_eq(&stored, &service_instance) {
+            if lifetime != Lifetime::Transient {
+                created.push(Created {
+                    key,
+                    lifetime,
+                    instance: service_instance,
+                });
+            }
+        } else {
+            // built concurrently by another thread, which was first
+            let lost = Stored {
+                sequence: 0,
+                instance: service_instance,
+                uninitialize: uninitialize::<Impl>,
+            };
+            if let Err(err) = Services::uninitialize_all(vec![(key, lost)]) {
+                error!(target: "di", "{}", err.message);
+            }
+        }
+        if let Some(parent) = parent {
+            parent.created.borrow_mut().extend(created);
         }
+        Ok(ServiceHandle::new(stored))
+    }
 
-        let service_instance = session_services.get(service_name).ok_or_else(|| {
-            Error::<ErrorCode>::new(
-                ErrorCode::CouldNotCreateSessionService,
-                format!(
-                    "Could not create session service for session {}",
-                    session.key()
+    fn create<Impl: 'static + Service + ?Sized>(
+        name: &str,
+        factory: &Prototype,
+        resolver: &Resolver,
+    ) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        let mut service_instance = factory.create(resolver)?;
+        let castable: &mut dyn Castable = service_instance.as_mut();
+        if let Some(service) = castable.query_mut::<Impl>() {
+            service.initialize().map_err(|err| {
+                Error::new(
+                    ErrorCode::CouldNotInitialize,
+                    format!("Could not initialize service {} ({})", name, err.message).as_str(),
                 )
-                .as_str(),
-            )
-        })?;
-
+            })?;
+        }
         Ok(service_instance)
     }
 
-    fn uninitialize_services_of_session(
-        &self,
-        session: HashMap<String, Arc<Mutex<Box<dyn Castable>>>>,
-    ) {
-        session
-            .iter()
-            .for_each(|service| Registry::unitialize_service(service.1, service.0));
+    /// Removes and uninitializes the services created for a service that
+    /// could not be built
+    fn roll_back(&self, session: &dyn Session, created: Vec<Created>) {
+        let services = match self.lock() {
+            Ok(mut registry) => created
+                .into_iter()
+                .filter_map(|created| {
+                    let instances = registry.instances(created.lifetime, session)?;
+                    let stored = instances.get(&created.key)?;
+                    if !Arc::ptr_eq(&stored.instance, &created.instance) {
+                        return None;
+                    }
+                    instances
+                        .remove(&created.key)
+                        .map(|stored| (created.key, stored))
+                })
+                .collect(),
+            Err(err) => {
+                error!(target: "di", "Could not roll back services ({})", err.message);
+                return;
+            }
+        };
+        if let Err(err) = Services::uninitialize_all(services) {
+            error!(target: "di", "{}", err.message);
+        }
     }
+}
 
-    fn unitialize_service(service: &Arc<Mutex<Box<dyn Castable>>>, name: &str) {
-        match service.lock() {
-            Ok(mut guard) => match guard.query_mut::<dyn Service>() {
-                Some(service) => service.uninitialize(),
-                None => error!(target: "di", "Could not uninitialize service {}", name),
-            },
-            Err(err) => {
-                error!(target: "di", "Could not uninitialize service {} ({})", name, err)
+impl Services {
+    /// Removes the instances of a binding from all sessions
+    fn take_instances(&mut self, key: &str) -> Vec<(String, Stored)> {
+        let mut services: Vec<(String, Stored)> = Vec::new();
+        if let Some(service) = self.singletons.remove(key) {
+            services.push((key.to_owned(), service));
+        }
+        self.available_sessions.values_mut().for_each(|session| {
+            if let Some(service) = session.remove(key) {
+                services.push((key.to_owned(), service));
             }
+        });
+        services
+    }
+
+    /// Instances of the services with the given lifetime, None for transient
+    /// services, which are not kept. Scoped services of a child session are
+    /// kept by its outermost session.
+    fn instances(&mut self, lifetime: Lifetime, session: &dyn Session) -> Option<&mut Instances> {
+        let key = match lifetime {
+            Lifetime::Singleton => return Some(&mut self.singletons),
+            Lifetime::Scoped => root(session).key(),
+            Lifetime::ChildScoped => session.key(),
+            Lifetime::Transient => return None,
+        };
+        Some(self.available_sessions.entry(key).or_default())
+    }
+
+    /// Slot of the instance of a binding, None for transient services
+    fn slot(key: &str, lifetime: Lifetime, session: &dyn Session) -> Option<Slot> {
+        let session = match lifetime {
+            Lifetime::Singleton => None,
+            Lifetime::Scoped => Some(root(session).key()),
+            Lifetime::ChildScoped => Some(session.key()),
+            Lifetime::Transient => return None,
+        };
+        Some((session, key.to_owned()))
+    }
+
+    /// Keeps the instance unless there is already one, and returns the kept
+    /// instance. None for transient services.
+    fn store<Impl: 'static + Service + ?Sized>(
+        &mut self,
+        name: &str,
+        lifetime: Lifetime,
+        session: &dyn Session,
+        service_instance: &Instance,
+    ) -> Option<Instance> {
+        let sequence = self.next_sequence;
+        let instances = self.instances(lifetime, session)?;
+        let stored = instances
+            .entry(name.to_owned())
+            .or_insert_with(|| Stored {
+                sequence,
+                instance: service_instance.clone(),
+                uninitialize: uninitialize::<Impl>,
+            })
+            .instance
+            .clone();
+        self.next_sequence += 1;
+        Some(stored)
+    }
+
+    /// Uninitializes the services, dependents before the services they depend
+    /// on. All services are uninitialized, the first failure is returned.
+    fn uninitialize_all(services: Vec<(String, Stored)>) -> Result<(), Error<ErrorCode>> {
+        Services::uninitialize_with(services, SharedService::write_blocking)
+    }
+
+    /// Uninitializes the services like uninitialize_all(), locking each one
+    /// with the given function
+    fn uninitialize_with(
+        mut services: Vec<(String, Stored)>,
+        lock: Lock,
+    ) -> Result<(), Error<ErrorCode>> {
+        services.sort_by_key(|(_, service)| std::cmp::Reverse(service.sequence));
+        let mut result = Ok(());
+        for (name, service) in services.iter() {
+            let uninitialized = Services::unitialize_service(service, name, lock);
+            if result.is_ok() {
+                result = uninitialized;
+            }
+        }
+        result
+    }
+
+    fn unitialize_service(
+        service: &Stored,
+        name: &str,
+        lock: Lock,
+    ) -> Result<(), Error<ErrorCode>> {
+        Services::uninitialized(name, lock(&service.instance), service.uninitialize)
+    }
+
+    /// Uninitializes the locked instance of the named service
+    fn uninitialized(
+        name: &str,
+        guard: Result<InstanceMut<'_>, Error<ErrorCode>>,
+        uninitialize: Uninitialize,
+    ) -> Result<(), Error<ErrorCode>> {
+        let mut guard = guard.map_err(|err| {
+            Error::new(
+                ErrorCode::CouldNotUninitialize,
+                format!("Could not uninitialize service {} ({})", name, err.message).as_str(),
+            )
+        })?;
+        // only services without lifecycle hooks are shared
+        if guard.is_shared() {
+            return Ok(());
+        }
+        match uninitialize(&mut *guard) {
+            Some(result) => result.map_err(|err| {
+                Error::new(
+                    ErrorCode::CouldNotUninitialize,
+                    format!("Could not uninitialize service {} ({})", name, err.message).as_str(),
+                )
+            }),
+            None => Err(Error::new(
+                ErrorCode::CouldNotUninitialize,
+                format!("Could not uninitialize service {}", name).as_str(),
+            )),
         }
     }
 }
 
+impl Drop for Services {
+    fn drop(&mut self) {
+        let mut services: Vec<(String, Stored)> =
+            std::mem::take(&mut self.singletons).into_iter().collect();
+        std::mem::take(&mut self.available_sessions)
+            .into_values()
+            .for_each(|session| services.extend(session));
+        if let Err(err) = Services::uninitialize_all(services) {
+            error!(target: "di", "{}", err.message);
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Registration of a service declared with #[service], collected at link time
+/// and applied by Registry::register_all()
+pub struct Registration {
+    register: fn(&Registry) -> Result<(), Error<ErrorCode>>,
+}
+
+impl Registration {
+    pub const fn new(register: fn(&Registry) -> Result<(), Error<ErrorCode>>) -> Registration {
+        Registration { register }
+    }
+}
+
+inventory::collect!(Registration);
+
 /// The one and only singleton of the DI registration
-static REGISTRY_INSTANCE: Lazy<Arc<Mutex<Registry>>> =
-    Lazy::new(|| Arc::new(Mutex::new(Registry::default())));
+static REGISTRY_INSTANCE: Lazy<Registry> = Lazy::new(Registry::default);
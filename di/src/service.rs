@@ -1,11 +1,369 @@
+use async_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use error::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use traitcast::Castable;
 
+use crate::registry::{ErrorCode, Resolver};
+
+/// A service is initialized after it is built. Failures are reported as
+/// ErrorCode::CouldNotInitialize and ErrorCode::CouldNotUninitialize.
 pub trait Service: Castable {
-    fn initialize(&mut self) {}
-    fn uninitialize(&mut self) {}
+    fn initialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        Ok(())
+    }
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        Ok(())
+    }
+    /// True if ServiceHandle::shared() may hand out the service as an Arc.
+    /// A service cannot be uninitialized while such an Arc is alive, so only
+    /// services without lifecycle hooks return true.
+    fn shareable(&self) -> bool {
+        false
+    }
 }
 
-pub type ServiceFactory = fn() -> Box<dyn Castable>;
+/// Builds a service. The resolver provides the services it depends on.
+/// Services are Sync, since several readers may use them at the same time.
+pub type ServiceFactory = fn(&Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>>;
+
+/// How long a service instance lives, chosen when the service is registered.
+/// Configurations spell it like #[service], e.g. child_scoped.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifetime {
+    /// One instance shared by all sessions
+    Singleton,
+    /// One instance per session, shared with the child sessions
+    Scoped,
+    /// One instance per child session, such as a request of a user session.
+    /// A session without parent is its own child.
+    ChildScoped,
+    /// A new instance on every get_service call. The caller owns it, it is
+    /// uninitialized when its last handle is dropped.
+    Transient,
+}
+impl fmt::Display for Lifetime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Lifetime::Singleton => "singleton",
+            Lifetime::Scoped => "scoped",
+            Lifetime::ChildScoped => "child_scoped",
+            Lifetime::Transient => "transient",
+        })
+    }
+}
 pub type ServiceName = fn() -> String;
 
-// use trait cast
+pub(crate) type Instance = Arc<SharedService>;
+/// Called with the instance when the last handle of a transient service is
+/// dropped
+pub(crate) type OnDrop = Box<dyn FnOnce(Result<InstanceMut<'_>, Error<ErrorCode>>) + Send + Sync>;
+
+/// Service instance shared by all its handles. The lock can be awaited, so
+/// async code waits for other users of the service without blocking its
+/// executor thread. Arcs of the service handed out by ServiceHandle::shared()
+/// are not locked, the service cannot be written while they are alive.
+pub(crate) struct SharedService {
+    service: RwLock<Arc<dyn Castable + Sync>>,
+    /// set if a writer panicked, the way std::sync::RwLock is poisoned
+    poisoned: AtomicBool,
+    on_drop: Option<OnDrop>,
+}
+
+impl SharedService {
+    pub(crate) fn new(service: Box<dyn Castable + Sync>) -> Instance {
+        Arc::new(SharedService {
+            service: RwLock::new(Arc::from(service)),
+            poisoned: AtomicBool::new(false),
+            on_drop: None,
+        })
+    }
+
+    /// Instance owned by its handles, on_drop is called when the last one is
+    /// dropped
+    pub(crate) fn transient(service: Box<dyn Castable + Sync>, on_drop: OnDrop) -> Instance {
+        Arc::new(SharedService {
+            service: RwLock::new(Arc::from(service)),
+            poisoned: AtomicBool::new(false),
+            on_drop: Some(on_drop),
+        })
+    }
+
+    /// Exclusive access to the instance without a cast, blocks the thread.
+    /// The instance cannot be written if it is shared, see
+    /// InstanceMut::is_shared().
+    pub(crate) fn write_blocking(&self) -> Result<InstanceMut<'_>, Error<ErrorCode>> {
+        self.check_poisoned::<dyn Castable>()?;
+        Ok(InstanceMut {
+            guard: self.service.write_blocking(),
+            poisoned: &self.poisoned,
+        })
+    }
+
+    /// Like write_blocking(), but fails instead of waiting if the instance
+    /// is in use
+    pub(crate) fn try_write(&self) -> Result<InstanceMut<'_>, Error<ErrorCode>> {
+        self.check_poisoned::<dyn Castable>()?;
+        let guard = self
+            .service
+            .try_write()
+            .ok_or_else(|| Error::new(ErrorCode::ServiceError, "Service is in use"))?;
+        Ok(InstanceMut {
+            guard,
+            poisoned: &self.poisoned,
+        })
+    }
+
+    fn check_poisoned<T: ?Sized>(&self) -> Result<(), Error<ErrorCode>> {
+        if self.poisoned.load(Ordering::Acquire) {
+            return Err(Error::new(
+                ErrorCode::ServiceError,
+                format!("Service {} is poisoned", std::any::type_name::<T>()).as_str(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SharedService {
+    fn drop(&mut self) {
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop(self.write_blocking());
+        }
+    }
+}
+
+/// Write guard of a SharedService, poisons the service if the thread panics
+/// while it is held
+pub(crate) struct InstanceMut<'a> {
+    guard: RwLockWriteGuard<'a, Arc<dyn Castable + Sync>>,
+    poisoned: &'a AtomicBool,
+}
+
+impl<'a> InstanceMut<'a> {
+    /// Fails if the service is shared by an Arc
+    fn new<T: ?Sized>(
+        guard: RwLockWriteGuard<'a, Arc<dyn Castable + Sync>>,
+        poisoned: &'a AtomicBool,
+    ) -> Result<InstanceMut<'a>, Error<ErrorCode>> {
+        let instance = InstanceMut { guard, poisoned };
+        if instance.is_shared() {
+            return Err(Error::new(
+                ErrorCode::ServiceError,
+                format!(
+                    "Service {} is shared and cannot be written",
+                    std::any::type_name::<T>()
+                )
+                .as_str(),
+            ));
+        }
+        Ok(instance)
+    }
+
+    /// True if Arcs of ServiceHandle::shared() are alive. Only services
+    /// without lifecycle hooks are shared, so they need no uninitialize.
+    pub(crate) fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.guard) > 1
+    }
+}
+
+impl Deref for InstanceMut<'_> {
+    type Target = dyn Castable + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        &**self.guard
+    }
+}
+
+impl DerefMut for InstanceMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        Arc::get_mut(&mut self.guard).expect("checked by InstanceMut::is_shared")
+    }
+}
+
+impl Drop for InstanceMut<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.poisoned.store(true, Ordering::Release);
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Service instance returned by get_service, typed by the service trait T.
+/// read() and write() lock the instance and cast it to T, blocking the
+/// thread. Async code uses read_async() and write_async() instead, which
+/// yield to the executor while the service is in use.
+pub struct ServiceHandle<T: ?Sized + 'static> {
+    instance: Instance,
+    service: PhantomData<fn() -> Box<T>>,
+}
+
+impl<T: ?Sized + 'static> ServiceHandle<T> {
+    pub(crate) fn new(instance: Instance) -> ServiceHandle<T> {
+        ServiceHandle {
+            instance,
+            service: PhantomData,
+        }
+    }
+
+    /// Shared access, other readers are not blocked
+    pub fn read(&self) -> Result<ServiceRef<'_, T>, Error<ErrorCode>> {
+        self.instance.check_poisoned::<T>()?;
+        self.cast_ref(self.instance.service.read_blocking())
+    }
+
+    /// Exclusive access
+    pub fn write(&self) -> Result<ServiceMut<'_, T>, Error<ErrorCode>> {
+        self.instance.check_poisoned::<T>()?;
+        self.cast_mut(self.instance.service.write_blocking())
+    }
+
+    /// Shared access, waits without blocking the executor
+    pub async fn read_async(&self) -> Result<ServiceRef<'_, T>, Error<ErrorCode>> {
+        self.instance.check_poisoned::<T>()?;
+        self.cast_ref(self.instance.service.read().await)
+    }
+
+    /// Exclusive access, waits without blocking the executor
+    pub async fn write_async(&self) -> Result<ServiceMut<'_, T>, Error<ErrorCode>> {
+        self.instance.check_poisoned::<T>()?;
+        self.cast_mut(self.instance.service.write().await)
+    }
+
+    /// True if both handles refer to the same instance
+    pub fn ptr_eq(&self, other: &ServiceHandle<T>) -> bool {
+        Arc::ptr_eq(&self.instance, &other.instance)
+    }
+
+    fn cast_ref<'a>(
+        &'a self,
+        guard: RwLockReadGuard<'a, Arc<dyn Castable + Sync>>,
+    ) -> Result<ServiceRef<'a, T>, Error<ErrorCode>> {
+        let castable: &dyn Castable = &**guard;
+        castable.query_ref::<T>().ok_or_else(unimplemented::<T>)?;
+        Ok(ServiceRef {
+            guard,
+            service: PhantomData,
+        })
+    }
+
+    fn cast_mut<'a>(
+        &'a self,
+        guard: RwLockWriteGuard<'a, Arc<dyn Castable + Sync>>,
+    ) -> Result<ServiceMut<'a, T>, Error<ErrorCode>> {
+        let mut guard = InstanceMut::new::<T>(guard, &self.instance.poisoned)?;
+        let castable: &mut dyn Castable = &mut *guard;
+        castable.query_mut::<T>().ok_or_else(unimplemented::<T>)?;
+        Ok(ServiceMut {
+            guard,
+            service: PhantomData,
+        })
+    }
+}
+
+impl<T: ?Sized + Service + 'static> ServiceHandle<T> {
+    /// Shared, owned reference to the service. The reference is not locked,
+    /// so the service cannot be written while it is alive. Fails unless the
+    /// service is Service::shareable().
+    pub fn shared(&self) -> Result<Arc<T>, Error<ErrorCode>> {
+        self.instance.check_poisoned::<T>()?;
+        let service = self.instance.service.read_blocking();
+        ServiceHandle::<T>::share(&service)
+    }
+
+    /// Shared, owned reference to the service, waits without blocking the
+    /// executor
+    pub async fn shared_async(&self) -> Result<Arc<T>, Error<ErrorCode>> {
+        self.instance.check_poisoned::<T>()?;
+        let service = self.instance.service.read().await;
+        ServiceHandle::<T>::share(&service)
+    }
+
+    fn share(service: &Arc<dyn Castable + Sync>) -> Result<Arc<T>, Error<ErrorCode>> {
+        let castable: &dyn Castable = &**service;
+        let shareable = castable
+            .query_ref::<T>()
+            .ok_or_else(unimplemented::<T>)?
+            .shareable();
+        if !shareable {
+            return Err(Error::new(
+                ErrorCode::ServiceError,
+                format!(
+                    "Service {} has lifecycle hooks and cannot be shared",
+                    std::any::type_name::<T>()
+                )
+                .as_str(),
+            ));
+        }
+        service
+            .clone()
+            .query_arc::<T>()
+            .ok_or_else(unimplemented::<T>)
+    }
+}
+
+impl<T: ?Sized + 'static> Clone for ServiceHandle<T> {
+    fn clone(&self) -> Self {
+        ServiceHandle::new(self.instance.clone())
+    }
+}
+
+fn unimplemented<T: ?Sized>() -> Error<ErrorCode> {
+    Error::new(
+        ErrorCode::Unimplemented,
+        format!(
+            "Service of type {} not implemented",
+            std::any::type_name::<T>()
+        )
+        .as_str(),
+    )
+}
+
+/// Read access to a service, see ServiceHandle::read()
+pub struct ServiceRef<'a, T: ?Sized + 'static> {
+    guard: RwLockReadGuard<'a, Arc<dyn Castable + Sync>>,
+    service: PhantomData<fn() -> Box<T>>,
+}
+
+impl<T: ?Sized + 'static> Deref for ServiceRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        let castable: &dyn Castable = &**self.guard;
+        castable
+            .query_ref::<T>()
+            .expect("cast checked by ServiceHandle::read")
+    }
+}
+
+/// Write access to a service, see ServiceHandle::write()
+pub struct ServiceMut<'a, T: ?Sized + 'static> {
+    guard: InstanceMut<'a>,
+    service: PhantomData<fn() -> Box<T>>,
+}
+
+impl<T: ?Sized + 'static> Deref for ServiceMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        let castable: &dyn Castable = &*self.guard;
+        castable
+            .query_ref::<T>()
+            .expect("cast checked by ServiceHandle::write")
+    }
+}
+
+impl<T: ?Sized + 'static> DerefMut for ServiceMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        let castable: &mut dyn Castable = &mut *self.guard;
+        castable
+            .query_mut::<T>()
+            .expect("cast checked by ServiceHandle::write")
+    }
+}
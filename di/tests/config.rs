@@ -0,0 +1,260 @@
+use serde::Deserialize;
+
+use di::config::{Configuration, Implementations};
+use di::registry::{ErrorCode, Registry, Resolver, SimpleSession};
+use di::service::{Lifetime, Service};
+
+use error::Error;
+
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+trait StorageService: Service {
+    fn describe(&self) -> String;
+}
+
+#[derive(Castable)]
+#[Traits(StorageService)]
+struct MemoryStorage {}
+impl MemoryStorage {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(MemoryStorage {}))
+    }
+}
+impl StorageService for MemoryStorage {
+    fn describe(&self) -> String {
+        "memory".to_owned()
+    }
+}
+impl Service for MemoryStorage {}
+
+mod cloud {
+    use di::service::Service;
+
+    /// Has the same short name as config::StorageService
+    pub trait StorageService: Service {}
+}
+
+#[derive(Deserialize)]
+struct FileParameters {
+    path: String,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Castable)]
+#[Traits(StorageService)]
+struct FileStorage {
+    path: String,
+    read_only: bool,
+}
+impl FileStorage {
+    pub fn factory(
+        _: &Resolver,
+        parameters: &FileParameters,
+    ) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(FileStorage {
+            path: parameters.path.clone(),
+            read_only: parameters.read_only,
+        }))
+    }
+}
+impl StorageService for FileStorage {
+    fn describe(&self) -> String {
+        format!("file {} read only {}", self.path, self.read_only)
+    }
+}
+impl Service for FileStorage {}
+
+fn implementations() -> Implementations {
+    let mut implementations = Implementations::default();
+    implementations
+        .add::<dyn StorageService>("memory", MemoryStorage::factory)
+        .add_configured::<dyn StorageService, _>("file", FileStorage::factory);
+    implementations
+}
+
+fn describe(container: &Registry, name: Option<&str>) -> String {
+    let session = SimpleSession::new();
+    let service = match name {
+        Some(name) => container.get_named_service::<dyn StorageService>(&session, name),
+        None => container.get_service::<dyn StorageService>(&session),
+    };
+    let description = service.unwrap().read().unwrap().describe();
+    description
+}
+
+fn configure(configuration: Result<Configuration, Error<ErrorCode>>) -> Result<Registry, String> {
+    let container = Registry::default();
+    configuration
+        .and_then(|configuration| container.configure(&implementations(), &configuration))
+        .map_err(|err| err.message)?;
+    Ok(container)
+}
+
+#[test]
+fn bind_services_from_toml() {
+    let container = configure(Configuration::from_toml(
+        r#"
+        ["dyn StorageService"]
+        implementation = "file"
+        lifetime = "singleton"
+        parameters = { path = "/tmp/store" }
+
+        ["dyn StorageService#cache"]
+        implementation = "memory"
+        "#,
+    ))
+    .unwrap();
+
+    assert_eq!(
+        describe(&container, None),
+        "file /tmp/store read only false"
+    );
+    assert_eq!(describe(&container, Some("cache")), "memory");
+
+    let registrations = container.introspect().unwrap().registrations;
+    assert_eq!(registrations[0].lifetime, Lifetime::Singleton);
+    assert_eq!(registrations[1].lifetime, Lifetime::Scoped);
+}
+
+#[test]
+fn bind_services_from_json() {
+    let container = configure(Configuration::from_json(
+        r#"{
+            "dyn config::StorageService": {
+                "implementation": "file",
+                "lifetime": "child_scoped",
+                "parameters": { "path": "/srv", "read_only": true }
+            }
+        }"#,
+    ))
+    .unwrap();
+
+    assert_eq!(describe(&container, None), "file /srv read only true");
+    let registrations = container.introspect().unwrap().registrations;
+    assert_eq!(registrations[0].lifetime, Lifetime::ChildScoped);
+}
+
+#[test]
+fn report_the_offending_key() {
+    let error = |text: &str| configure(Configuration::from_toml(text)).err().unwrap();
+
+    assert_eq!(
+        error("[\"dyn StorageService\"]\nimplementation = \"cloud\""),
+        "Invalid configuration of dyn StorageService (unknown implementation cloud)"
+    );
+    assert_eq!(
+        error("[\"dyn QueueService\"]\nimplementation = \"memory\""),
+        "Invalid configuration of dyn QueueService (unknown service)"
+    );
+    assert_eq!(
+        error("[\"dyn StorageService#backup\"]\nimplementation = \"file\""),
+        "Invalid configuration of dyn StorageService#backup \
+         (invalid parameters: missing field `path`)"
+    );
+    assert_eq!(
+        error("[\"dyn StorageService\"]\nimplementation = \"memory\"\nparameters = { size = 1 }"),
+        "Invalid configuration of dyn StorageService \
+         (the implementation takes no parameters)"
+    );
+    assert_eq!(
+        error("[\"dyn StorageService\"]\nimplementation = \"memory\"\nlifetime = \"forever\""),
+        "Invalid configuration of dyn StorageService (unknown variant `forever`, \
+         expected one of `singleton`, `scoped`, `child_scoped`, `transient`)"
+    );
+    assert_eq!(
+        error("[\"dyn StorageService\"]\nimplementation = "),
+        "Invalid configuration (line 2: invalid syntax)"
+    );
+    assert_eq!(
+        error("[\"dyn StorageService\"\nimplementation = \"memory\""),
+        "Invalid configuration (line 1: invalid table header, expected `.`, `]`)"
+    );
+}
+
+#[test]
+fn bind_nothing_from_an_invalid_configuration() {
+    let container = Registry::default();
+    let configuration = Configuration::from_toml(
+        r#"
+        ["dyn StorageService"]
+        implementation = "memory"
+
+        ["dyn StorageService#cache"]
+        implementation = "disk"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        container
+            .configure(&implementations(), &configuration)
+            .err()
+            .unwrap(),
+        Error::new(ErrorCode::InvalidConfiguration, "")
+    );
+    assert!(container.introspect().unwrap().registrations.is_empty());
+}
+
+#[test]
+fn bind_nothing_if_a_service_is_already_registered() {
+    let container = Registry::default();
+    container
+        .register_named_service::<dyn StorageService>(
+            "cache",
+            MemoryStorage::factory,
+            Lifetime::Scoped,
+        )
+        .unwrap();
+    let configuration = Configuration::from_toml(
+        r#"
+        ["dyn StorageService"]
+        implementation = "file"
+        parameters = { path = "/tmp/store" }
+
+        ["dyn StorageService#cache"]
+        implementation = "memory"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        container
+            .configure(&implementations(), &configuration)
+            .err()
+            .unwrap(),
+        Error::new(ErrorCode::AlreadyRegisteredService, "")
+    );
+    let registrations = container.introspect().unwrap().registrations;
+    assert_eq!(registrations.len(), 1);
+    assert_eq!(registrations[0].name.as_deref(), Some("cache"));
+    assert_eq!(describe(&container, Some("cache")), "memory");
+}
+
+#[test]
+fn report_an_ambiguous_short_name() {
+    let mut implementations = implementations();
+    implementations.add::<dyn cloud::StorageService>("memory", MemoryStorage::factory);
+    let configuration = |text: &str| Configuration::from_toml(text).unwrap();
+    let container = Registry::default();
+
+    assert_eq!(
+        container
+            .configure(
+                &implementations,
+                &configuration("[\"dyn StorageService\"]\nimplementation = \"memory\""),
+            )
+            .err()
+            .unwrap()
+            .message,
+        "Invalid configuration of dyn StorageService (ambiguous service, matches \
+         dyn config::StorageService, dyn config::cloud::StorageService)"
+    );
+    assert!(container
+        .configure(
+            &implementations,
+            &configuration("[\"dyn config::cloud::StorageService\"]\nimplementation = \"memory\""),
+        )
+        .is_ok());
+}
@@ -0,0 +1,177 @@
+use di::introspection::{DependencyInfo, RegistrationInfo, SessionInfo};
+use di::registry::{ErrorCode, Registry, Resolver, Session, SimpleSession};
+use di::service::{Lifetime, Service, ServiceHandle};
+
+use error::Error;
+
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+trait ConfigService: Service {}
+#[derive(Castable)]
+#[Traits(ConfigService)]
+struct ConfigServiceImpl {}
+impl ConfigServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(ConfigServiceImpl {}))
+    }
+}
+impl ConfigService for ConfigServiceImpl {}
+impl Service for ConfigServiceImpl {}
+
+trait StoreService: Service {}
+#[derive(Castable)]
+#[Traits(StoreService)]
+struct StoreServiceImpl {
+    _config: ServiceHandle<dyn ConfigService>,
+}
+impl StoreServiceImpl {
+    pub fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(StoreServiceImpl {
+            _config: resolver.get_service::<dyn ConfigService>()?,
+        }))
+    }
+}
+impl StoreService for StoreServiceImpl {}
+impl Service for StoreServiceImpl {}
+
+trait CacheService: Service {}
+#[derive(Castable)]
+#[Traits(CacheService)]
+struct CacheServiceImpl {}
+impl CacheServiceImpl {
+    /// Depends on a service nobody registered
+    pub fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        resolver.get_service::<dyn ConfigService>()?;
+        resolver.get_named_service::<dyn StoreService>("replica")?;
+        Ok(Box::new(CacheServiceImpl {}))
+    }
+}
+impl CacheService for CacheServiceImpl {}
+impl Service for CacheServiceImpl {}
+
+const CONFIG: &str = "dyn introspection::ConfigService";
+const STORE: &str = "dyn introspection::StoreService";
+const CACHE: &str = "dyn introspection::CacheService";
+const REPLICA: &str = "dyn introspection::StoreService#replica";
+
+fn registry() -> Registry {
+    let container = Registry::default();
+    container
+        .register_service::<dyn ConfigService>(ConfigServiceImpl::factory, Lifetime::Singleton)
+        .unwrap();
+    container
+        .register_service::<dyn StoreService>(StoreServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_named_service::<dyn StoreService>(
+            "primary",
+            StoreServiceImpl::factory,
+            Lifetime::Transient,
+        )
+        .unwrap();
+    container
+        .register_service::<dyn CacheService>(CacheServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+}
+
+#[test]
+fn list_registrations() {
+    let container = registry();
+    let _mock = container
+        .override_service::<dyn ConfigService>(ConfigServiceImpl::factory)
+        .unwrap();
+
+    let info = container.introspect().unwrap();
+    assert_eq!(
+        info.registrations[1],
+        RegistrationInfo {
+            key: CONFIG.to_owned(),
+            service: CONFIG.to_owned(),
+            name: None,
+            lifetime: Lifetime::Singleton,
+            overridden: true,
+        }
+    );
+    assert_eq!(
+        info.registrations[3],
+        RegistrationInfo {
+            key: format!("{}#primary", STORE),
+            service: STORE.to_owned(),
+            name: Some("primary".to_owned()),
+            lifetime: Lifetime::Transient,
+            overridden: false,
+        }
+    );
+    assert_eq!(info.registrations.len(), 4);
+}
+
+#[test]
+fn list_sessions_and_instances() {
+    let container = registry();
+    let session = SimpleSession::new();
+    container.get_service::<dyn StoreService>(&session).unwrap();
+    container
+        .get_named_service::<dyn StoreService>(&session, "primary")
+        .unwrap();
+
+    let info = container.introspect().unwrap();
+    assert_eq!(info.singletons, vec![CONFIG]);
+    assert_eq!(
+        info.sessions,
+        vec![SessionInfo {
+            key: session.key(),
+            services: vec![STORE.to_owned()],
+        }]
+    );
+
+    container.clear_session(&session).unwrap();
+    assert!(container.introspect().unwrap().sessions.is_empty());
+}
+
+#[test]
+fn list_dependencies() {
+    let container = registry();
+    let session = SimpleSession::new();
+    container.get_service::<dyn StoreService>(&session).unwrap();
+    assert!(container.get_service::<dyn CacheService>(&session).is_err());
+
+    let info = container.introspect().unwrap();
+    let dependency = |service: &str, dependency: &str| DependencyInfo {
+        service: service.to_owned(),
+        dependency: dependency.to_owned(),
+    };
+    assert_eq!(
+        info.dependencies,
+        vec![
+            dependency(CACHE, CONFIG),
+            dependency(CACHE, REPLICA),
+            dependency(STORE, CONFIG),
+        ]
+    );
+}
+
+#[test]
+fn export_the_dependency_graph() {
+    let container = registry();
+    let session = SimpleSession::new();
+    container.get_service::<dyn StoreService>(&session).unwrap();
+    assert!(container.get_service::<dyn CacheService>(&session).is_err());
+
+    let dot = container.introspect().unwrap().to_dot();
+    assert!(dot.starts_with("digraph di {\n"));
+    assert!(dot.contains(&format!(
+        "    \"{}\" [label=\"{}\\nsingleton\"];\n",
+        CONFIG, CONFIG
+    )));
+    assert!(dot.contains(&format!("    \"{}\" -> \"{}\";\n", STORE, CONFIG)));
+    assert!(dot.contains(&format!("    \"{}\" [style=dashed];\n", REPLICA)));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&container.introspect().unwrap().to_json()).unwrap();
+    assert_eq!(json["registrations"][0]["key"], CACHE);
+    assert_eq!(json["registrations"][0]["lifetime"], "scoped");
+    assert_eq!(json["dependencies"][2]["service"], STORE);
+    assert_eq!(json["singletons"][0], CONFIG);
+}
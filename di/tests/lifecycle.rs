@@ -0,0 +1,308 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use di::registry::{ErrorCode, Registry, Resolver, SimpleSession};
+use di::service::{Lifetime, Service, ServiceHandle};
+use std::sync::Arc;
+
+use error::Error;
+
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+trait CounterService: Service {
+    fn next(&mut self) -> u32;
+}
+#[derive(Castable)]
+#[Traits(CounterService)]
+struct CounterServiceImpl {
+    counter: u32,
+}
+impl CounterServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(CounterServiceImpl { counter: 0 }))
+    }
+}
+impl CounterService for CounterServiceImpl {
+    fn next(&mut self) -> u32 {
+        let res = self.counter;
+        self.counter += 1;
+        res
+    }
+}
+impl Service for CounterServiceImpl {}
+
+trait FailingService: Service {}
+#[derive(Castable)]
+#[Traits(FailingService)]
+struct FailingServiceImpl {}
+impl FailingServiceImpl {
+    /// Uses the counter service before failing, so the counter is created
+    pub fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        let counter = resolver.get_service::<dyn CounterService>()?;
+        counter.write()?.next();
+        Ok(Box::new(FailingServiceImpl {}))
+    }
+}
+impl FailingService for FailingServiceImpl {}
+impl Service for FailingServiceImpl {
+    fn initialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        Err(Error::new(ErrorCode::ServiceError, "Could not open file"))
+    }
+}
+
+fn next_of(container: &Registry, session: &SimpleSession) -> u32 {
+    let service = container
+        .get_service::<dyn CounterService>(session)
+        .unwrap();
+    let mut service = service.write().unwrap();
+    service.next()
+}
+
+#[test]
+fn fail_to_initialize_a_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn FailingService>(FailingServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn CounterService>(CounterServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let session = SimpleSession::new();
+
+    let service = container.get_service::<dyn FailingService>(&session);
+    assert_eq!(
+        service.err().unwrap(),
+        Error::new(ErrorCode::CouldNotInitialize, "")
+    );
+    // a failed service is not kept, it is tried again
+    let service = container.get_service::<dyn FailingService>(&session);
+    assert!(service.is_err());
+}
+
+#[test]
+fn roll_back_dependencies_of_a_failed_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn FailingService>(FailingServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn CounterService>(CounterServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let session = SimpleSession::new();
+
+    assert!(container
+        .get_service::<dyn FailingService>(&session)
+        .is_err());
+    // the counter created for the failed service is gone
+    assert_eq!(next_of(&container, &session), 0);
+}
+
+#[test]
+fn keep_existing_dependencies_of_a_failed_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn FailingService>(FailingServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn CounterService>(CounterServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let session = SimpleSession::new();
+
+    assert_eq!(next_of(&container, &session), 0);
+    assert!(container
+        .get_service::<dyn FailingService>(&session)
+        .is_err());
+    assert_eq!(next_of(&container, &session), 2);
+}
+
+static SHUTDOWN: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+trait FirstService: Service {}
+#[derive(Castable)]
+#[Traits(FirstService)]
+struct FirstServiceImpl {}
+impl FirstServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(FirstServiceImpl {}))
+    }
+}
+impl FirstService for FirstServiceImpl {}
+impl Service for FirstServiceImpl {
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        SHUTDOWN.lock().unwrap().push("first");
+        Ok(())
+    }
+}
+
+trait SecondService: Service {}
+#[derive(Castable)]
+#[Traits(SecondService)]
+struct SecondServiceImpl {
+    _first: ServiceHandle<dyn FirstService>,
+}
+impl SecondServiceImpl {
+    pub fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(SecondServiceImpl {
+            _first: resolver.get_service::<dyn FirstService>()?,
+        }))
+    }
+}
+impl SecondService for SecondServiceImpl {}
+impl Service for SecondServiceImpl {
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        SHUTDOWN.lock().unwrap().push("second");
+        Ok(())
+    }
+}
+
+#[test]
+fn uninitialize_dependents_first() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SecondService>(SecondServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn FirstService>(FirstServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let session = SimpleSession::new();
+    container
+        .get_service::<dyn SecondService>(&session)
+        .unwrap();
+
+    container.clear_session(&session).unwrap();
+    assert_eq!(*SHUTDOWN.lock().unwrap(), vec!["second", "first"]);
+}
+
+trait StubbornService: Service {}
+#[derive(Castable)]
+#[Traits(StubbornService)]
+struct StubbornServiceImpl {}
+impl StubbornServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(StubbornServiceImpl {}))
+    }
+}
+impl StubbornService for StubbornServiceImpl {}
+impl Service for StubbornServiceImpl {
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        Err(Error::new(ErrorCode::ServiceError, "Could not close file"))
+    }
+}
+
+#[test]
+fn fail_to_uninitialize_a_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn StubbornService>(StubbornServiceImpl::factory, Lifetime::Singleton)
+        .unwrap();
+    let session = SimpleSession::new();
+    container
+        .get_service::<dyn StubbornService>(&session)
+        .unwrap();
+
+    let result = container.unregister_service::<dyn StubbornService>();
+    assert_eq!(
+        result.err().unwrap(),
+        Error::new(ErrorCode::CouldNotUninitialize, "")
+    );
+    // the service is unregistered anyway
+    let service = container.get_service::<dyn StubbornService>(&session);
+    assert_eq!(
+        service.err().unwrap(),
+        Error::new(ErrorCode::UnregisteredService, "")
+    );
+}
+
+static CLOSED: AtomicU32 = AtomicU32::new(0);
+
+trait ResourceService: Service {}
+#[derive(Castable)]
+#[Traits(ResourceService)]
+struct ResourceServiceImpl {}
+impl ResourceServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(ResourceServiceImpl {}))
+    }
+}
+impl ResourceService for ResourceServiceImpl {}
+impl Service for ResourceServiceImpl {
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        CLOSED.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[test]
+fn uninitialize_a_transient_service_with_its_last_handle() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn ResourceService>(ResourceServiceImpl::factory, Lifetime::Transient)
+        .unwrap();
+    let session = SimpleSession::new();
+    let first = container
+        .get_service::<dyn ResourceService>(&session)
+        .unwrap();
+    let copy = first.clone();
+    let second = container
+        .get_service::<dyn ResourceService>(&session)
+        .unwrap();
+
+    drop(first);
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 0);
+    drop(copy);
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 1);
+    // clearing the session does not touch the instances of the caller
+    container.clear_session(&session).unwrap();
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 1);
+    drop(second);
+    assert_eq!(CLOSED.load(Ordering::SeqCst), 2);
+}
+
+trait SharedCounterService: Service {}
+#[derive(Castable)]
+#[Traits(SharedCounterService)]
+struct SharedCounterServiceImpl {}
+impl SharedCounterServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(SharedCounterServiceImpl {}))
+    }
+}
+impl SharedCounterService for SharedCounterServiceImpl {}
+impl Service for SharedCounterServiceImpl {
+    fn shareable(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn share_only_services_without_hooks() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn StubbornService>(StubbornServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn SharedCounterService>(
+            SharedCounterServiceImpl::factory,
+            Lifetime::Scoped,
+        )
+        .unwrap();
+    let session = SimpleSession::new();
+
+    // its uninitialize hook could not run while an Arc is alive
+    let stubborn = container
+        .get_service::<dyn StubbornService>(&SimpleSession::new())
+        .unwrap();
+    assert_eq!(
+        stubborn.shared().err(),
+        Some(Error::new(ErrorCode::ServiceError, ""))
+    );
+
+    let shared: Arc<dyn SharedCounterService> = container
+        .get_service::<dyn SharedCounterService>(&session)
+        .unwrap()
+        .shared()
+        .unwrap();
+    assert_eq!(container.clear_session(&session), Ok(()));
+    drop(shared);
+}
@@ -0,0 +1,210 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use di::registry::{ErrorCode, Registry, Resolver, Session, SimpleSession};
+use di::service::{Lifetime, Service};
+
+use error::Error;
+
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+trait NameService: Service {
+    fn name(&self) -> &str;
+    fn count(&mut self) -> u32;
+}
+#[derive(Castable)]
+#[Traits(NameService)]
+struct NameServiceImpl {
+    name: &'static str,
+    counter: u32,
+}
+impl NameServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(NameServiceImpl {
+            name: "real",
+            counter: 0,
+        }))
+    }
+    pub fn mock(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(NameServiceImpl {
+            name: "mock",
+            counter: 0,
+        }))
+    }
+    pub fn other_mock(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(NameServiceImpl {
+            name: "other mock",
+            counter: 0,
+        }))
+    }
+}
+impl NameService for NameServiceImpl {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn count(&mut self) -> u32 {
+        let res = self.counter;
+        self.counter += 1;
+        res
+    }
+}
+impl Service for NameServiceImpl {}
+
+fn name_of(container: &Registry, session: &dyn Session) -> String {
+    let service = container.get_service::<dyn NameService>(session).unwrap();
+    let service = service.read().unwrap();
+    service.name().to_owned()
+}
+fn count_of(container: &Registry, session: &dyn Session) -> u32 {
+    let service = container.get_service::<dyn NameService>(session).unwrap();
+    let mut service = service.write().unwrap();
+    service.count()
+}
+
+#[test]
+fn override_a_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn NameService>(NameServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let session = container.scope();
+
+    let mock = container
+        .override_service::<dyn NameService>(NameServiceImpl::mock)
+        .unwrap();
+    assert_eq!(name_of(&container, &session), "mock");
+    drop(mock);
+    assert_eq!(name_of(&container, &session), "real");
+}
+
+#[test]
+fn keep_the_instances_of_an_overridden_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn NameService>(NameServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let session = SimpleSession::new();
+    assert_eq!(count_of(&container, &session), 0);
+
+    {
+        let _mock = container
+            .override_service::<dyn NameService>(NameServiceImpl::mock)
+            .unwrap();
+        assert_eq!(count_of(&container, &session), 0);
+        assert_eq!(count_of(&container, &session), 1);
+    }
+    // the real service of the session was not rebuilt
+    assert_eq!(count_of(&container, &session), 1);
+}
+
+#[test]
+fn override_an_overridden_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn NameService>(NameServiceImpl::factory, Lifetime::Singleton)
+        .unwrap();
+    let session = container.scope();
+
+    let mock = container
+        .override_service::<dyn NameService>(NameServiceImpl::mock)
+        .unwrap();
+    let other_mock = container
+        .override_service::<dyn NameService>(NameServiceImpl::other_mock)
+        .unwrap();
+    assert_eq!(name_of(&container, &session), "other mock");
+    drop(other_mock);
+    assert_eq!(name_of(&container, &session), "mock");
+    drop(mock);
+    assert_eq!(name_of(&container, &session), "real");
+}
+
+#[test]
+fn override_an_unregistered_service() {
+    let container = Registry::default();
+    let session = container.scope();
+
+    let mock = container
+        .override_service::<dyn NameService>(NameServiceImpl::mock)
+        .unwrap();
+    assert_eq!(name_of(&container, &session), "mock");
+    drop(mock);
+    assert_eq!(
+        container
+            .get_service::<dyn NameService>(&session)
+            .err()
+            .unwrap(),
+        Error::new(ErrorCode::UnregisteredService, "")
+    );
+}
+
+#[test]
+fn override_one_of_several_implementations() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn NameService>(NameServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_named_service::<dyn NameService>(
+            "other",
+            NameServiceImpl::factory,
+            Lifetime::Scoped,
+        )
+        .unwrap();
+    let session = container.scope();
+
+    let _mock = container
+        .override_service::<dyn NameService>(NameServiceImpl::mock)
+        .unwrap();
+    let names: Vec<String> = container
+        .get_services::<dyn NameService>(&session)
+        .unwrap()
+        .iter()
+        .map(|service| service.read().unwrap().name().to_owned())
+        .collect();
+    assert_eq!(names, vec!["mock", "real"]);
+}
+
+static UNINITIALIZED: AtomicU32 = AtomicU32::new(0);
+
+trait TrackedService: Service {}
+#[derive(Castable)]
+#[Traits(TrackedService)]
+struct TrackedServiceImpl {}
+impl TrackedServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(TrackedServiceImpl {}))
+    }
+}
+impl TrackedService for TrackedServiceImpl {}
+impl Service for TrackedServiceImpl {
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        UNINITIALIZED.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[test]
+fn clear_a_scope_and_an_override() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn TrackedService>(TrackedServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+
+    let session = container.scope();
+    container
+        .get_service::<dyn TrackedService>(&session)
+        .unwrap();
+    drop(session);
+    assert_eq!(UNINITIALIZED.load(Ordering::SeqCst), 1);
+
+    let session = container.scope();
+    let mock = container
+        .override_service::<dyn TrackedService>(TrackedServiceImpl::factory)
+        .unwrap();
+    container
+        .get_service::<dyn TrackedService>(&session)
+        .unwrap();
+    drop(mock);
+    assert_eq!(UNINITIALIZED.load(Ordering::SeqCst), 2);
+}
@@ -1,8 +1,9 @@
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::thread;
 use std::time::Duration;
 
-use di::registry::{ErrorCode, Registry, SimpleSession};
-use di::service::Service;
+use di::registry::{ErrorCode, Registry, Resolver, SimpleSession};
+use di::service::{Lifetime, Service, ServiceHandle};
 
 use error::Error;
 
@@ -19,8 +20,8 @@ struct SimpleServiceImpl {
     counter: u32,
 }
 impl SimpleServiceImpl {
-    pub fn factory() -> Box<dyn Castable> {
-        Box::new(SimpleServiceImpl { counter: 0 })
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(SimpleServiceImpl { counter: 0 }))
     }
 }
 
@@ -41,8 +42,9 @@ impl SimpleService for SimpleServiceImpl {
     }
 }
 impl Service for SimpleServiceImpl {
-    fn initialize(&mut self) {
-        println!("service initialized!")
+    fn initialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        println!("service initialized!");
+        Ok(())
     }
 }
 
@@ -50,8 +52,8 @@ impl Service for SimpleServiceImpl {
 #[Traits(SimpleService)]
 struct AnotherSimpleServiceImpl {}
 impl AnotherSimpleServiceImpl {
-    pub fn factory() -> Box<dyn Castable> {
-        Box::new(AnotherSimpleServiceImpl {})
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(AnotherSimpleServiceImpl {}))
     }
 }
 impl SimpleService for AnotherSimpleServiceImpl {
@@ -65,10 +67,60 @@ impl SimpleService for AnotherSimpleServiceImpl {
 }
 impl Service for AnotherSimpleServiceImpl {}
 
+trait DependentService: Service {
+    fn bar(&mut self) -> u32;
+}
+#[derive(Castable)]
+#[Traits(DependentService)]
+struct DependentServiceImpl {
+    simple: ServiceHandle<dyn SimpleService>,
+}
+impl DependentServiceImpl {
+    pub fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(DependentServiceImpl {
+            simple: resolver.get_service::<dyn SimpleService>()?,
+        }))
+    }
+}
+impl DependentService for DependentServiceImpl {
+    fn bar(&mut self) -> u32 {
+        self.simple.write().unwrap().bar()
+    }
+}
+impl Service for DependentServiceImpl {}
+
+trait CyclicService: Service {}
+#[derive(Castable)]
+#[Traits(CyclicService)]
+struct CyclicServiceImpl {}
+impl CyclicServiceImpl {
+    pub fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        resolver.get_service::<dyn OtherCyclicService>()?;
+        Ok(Box::new(CyclicServiceImpl {}))
+    }
+}
+impl CyclicService for CyclicServiceImpl {}
+impl Service for CyclicServiceImpl {}
+
+trait OtherCyclicService: Service {}
+#[derive(Castable)]
+#[Traits(OtherCyclicService)]
+struct OtherCyclicServiceImpl {}
+impl OtherCyclicServiceImpl {
+    pub fn factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        resolver.get_service::<dyn CyclicService>()?;
+        Ok(Box::new(OtherCyclicServiceImpl {}))
+    }
+}
+impl OtherCyclicService for OtherCyclicServiceImpl {}
+impl Service for OtherCyclicServiceImpl {}
+
 #[test]
 fn get_unregistered_service() {
     assert_eq!(
-        Registry::get_service::<dyn SimpleService>(&SimpleSession::default()).err(),
+        Registry::global()
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .err(),
         Some(Error::<ErrorCode>::new(ErrorCode::UnregisteredService, ""))
     );
 }
@@ -76,150 +128,586 @@ fn get_unregistered_service() {
 #[test]
 fn register_a_simple_service() {
     assert_eq!(
-        Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory),
+        Registry::global()
+            .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped),
         Ok(())
     );
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
 }
 #[test]
 fn register_already_registered_service() {
     assert_eq!(
-        Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory),
+        Registry::global()
+            .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped),
         Ok(())
     );
     assert_eq!(
-        Registry::register_service::<dyn SimpleService>(AnotherSimpleServiceImpl::factory),
+        Registry::global().register_service::<dyn SimpleService>(
+            AnotherSimpleServiceImpl::factory,
+            Lifetime::Scoped
+        ),
         Err(Error::new(ErrorCode::AlreadyRegisteredService, ""))
     );
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
 }
 #[test]
 fn unregister_registered_service() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).ok();
-    assert_eq!(Registry::unregister_service::<dyn SimpleService>(), Ok(()));
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .ok();
+    assert_eq!(
+        Registry::global().unregister_service::<dyn SimpleService>(),
+        Ok(())
+    );
     assert_eq!(
-        Registry::get_service::<dyn SimpleService>(&SimpleSession::default()).err(),
+        Registry::global()
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .err(),
         Some(Error::new(ErrorCode::UnregisteredService, ""))
     );
 }
 #[test]
 fn create_an_application_service() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).ok();
-    let service = Registry::get_service::<dyn SimpleService>(&SimpleSession::default());
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .ok();
+    let service = Registry::global().get_service::<dyn SimpleService>(&SimpleSession::default());
     assert_eq!(service.is_ok(), true);
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
 }
 #[test]
 fn remove_session() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).ok();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .ok();
     {
-        let service = Registry::get_service::<dyn SimpleService>(&SimpleSession::default());
+        let service =
+            Registry::global().get_service::<dyn SimpleService>(&SimpleSession::default());
         assert_eq!(service.is_ok(), true);
     }
     assert_eq!(
-        Registry::clear_session(&SimpleSession::default()).is_ok(),
+        Registry::global()
+            .clear_session(&SimpleSession::default())
+            .is_ok(),
         true
     );
 
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
 }
 #[test]
 fn cast_a_service() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).ok();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .ok();
     {
-        let service = Registry::get_service::<dyn SimpleService>(&SimpleSession::default());
+        let service =
+            Registry::global().get_service::<dyn SimpleService>(&SimpleSession::default());
         assert_eq!(service.is_ok(), true);
 
-        let service = service.unwrap().clone();
-        let service = service.lock().unwrap();
-        let service = service.as_ref().query_ref::<dyn SimpleService>();
-        assert_eq!(service.is_some(), true);
+        let service = service.unwrap();
+        let service = service.read();
+        assert_eq!(service.is_ok(), true);
         assert_eq!(service.unwrap().foo(), true);
     }
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
 }
 #[test]
 fn cast_a_mutable_service() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).ok();
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .ok();
     {
-        let service = Registry::get_service::<dyn SimpleService>(&SimpleSession::default());
+        let service =
+            Registry::global().get_service::<dyn SimpleService>(&SimpleSession::default());
         assert_eq!(service.is_ok(), true);
 
-        let service = service.unwrap().clone();
-        let mut service = service.lock().unwrap();
-        let service = service.as_mut().query_mut::<dyn SimpleService>();
-        assert_eq!(service.is_some(), true);
+        let service = service.unwrap();
+        let service = service.write();
+        assert_eq!(service.is_ok(), true);
         assert_eq!(service.unwrap().bar(), 0);
     }
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
 }
 #[test]
 fn get_same_service_in_a_thread() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).ok();
-    let service = Registry::get_service::<dyn SimpleService>(&SimpleSession::default());
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .ok();
+    let service = Registry::global().get_service::<dyn SimpleService>(&SimpleSession::default());
 
     let t = thread::spawn(move || {
-        let service = Registry::get_service::<dyn SimpleService>(&SimpleSession::default());
-        let service = service.unwrap().clone();
+        let service =
+            Registry::global().get_service::<dyn SimpleService>(&SimpleSession::default());
+        let service = service.unwrap();
 
         thread::sleep(Duration::from_millis(200));
         {
-            let mut service = service.lock().unwrap();
-            let service = service.as_mut().query_mut::<dyn SimpleService>();
-            assert_eq!(service.is_some(), true);
+            let service = service.write();
+            assert_eq!(service.is_ok(), true);
             assert_eq!(service.unwrap().bar(), 1);
         }
     });
 
     assert_eq!(service.is_ok(), true);
     {
-        let service = service.unwrap().clone();
-        let mut service = service.lock().unwrap();
-        let service = service.as_mut().query_mut::<dyn SimpleService>();
-        assert_eq!(service.is_some(), true);
+        let service = service.unwrap();
+        let service = service.write();
+        assert_eq!(service.is_ok(), true);
         assert_eq!(service.unwrap().bar(), 0);
     }
     thread::sleep(Duration::from_millis(100));
 
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
     assert_eq!(
-        Registry::get_service::<dyn SimpleService>(&SimpleSession::default()).err(),
+        Registry::global()
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .err(),
         Some(Error::new(ErrorCode::UnregisteredService, ""))
     );
     t.join().unwrap();
 }
 #[test]
 fn get_service_for_other_session() {
-    Registry::register_service::<dyn SimpleService>(SimpleServiceImpl::factory).ok();
-    let service = Registry::get_service::<dyn SimpleService>(&SimpleSession::default());
+    Registry::global()
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .ok();
+    let service = Registry::global().get_service::<dyn SimpleService>(&SimpleSession::default());
 
     let t = thread::spawn(move || {
         let session = SimpleSession::new();
-        let service = Registry::get_service::<dyn SimpleService>(&session);
-        let service = service.unwrap().clone();
+        let service = Registry::global().get_service::<dyn SimpleService>(&session);
+        let service = service.unwrap();
 
         {
-            let mut service = service.lock().unwrap();
-            let service = service.as_mut().query_mut::<dyn SimpleService>();
-            assert_eq!(service.is_some(), true);
+            let service = service.write();
+            assert_eq!(service.is_ok(), true);
             assert_eq!(service.unwrap().bar(), 0);
         }
     });
 
     assert_eq!(service.is_ok(), true);
     {
-        let service = service.unwrap().clone();
-        let mut service = service.lock().unwrap();
-        let service = service.as_mut().query_mut::<dyn SimpleService>();
-        assert_eq!(service.is_some(), true);
+        let service = service.unwrap();
+        let service = service.write();
+        assert_eq!(service.is_ok(), true);
         assert_eq!(service.unwrap().bar(), 0);
     }
     thread::sleep(Duration::from_millis(100));
 
-    Registry::unregister_service::<dyn SimpleService>().ok();
+    Registry::global()
+        .unregister_service::<dyn SimpleService>()
+        .ok();
     assert_eq!(
-        Registry::get_service::<dyn SimpleService>(&SimpleSession::default()).err(),
+        Registry::global()
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .err(),
         Some(Error::new(ErrorCode::UnregisteredService, ""))
     );
     t.join().unwrap();
 }
+#[test]
+fn register_a_service_in_independent_containers() {
+    let first = Registry::default();
+    let second = Registry::default();
+    assert_eq!(
+        first.register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped),
+        Ok(())
+    );
+    assert_eq!(
+        second.register_service::<dyn SimpleService>(
+            AnotherSimpleServiceImpl::factory,
+            Lifetime::Scoped
+        ),
+        Ok(())
+    );
+
+    {
+        let service = first
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .unwrap();
+        assert_eq!(service.read().unwrap().foo(), true);
+        let service = second
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .unwrap();
+        assert_eq!(service.read().unwrap().foo(), false);
+    }
+
+    assert_eq!(first.unregister_service::<dyn SimpleService>(), Ok(()));
+    assert_eq!(
+        first
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .err(),
+        Some(Error::new(ErrorCode::UnregisteredService, ""))
+    );
+    assert_eq!(
+        second
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .is_ok(),
+        true
+    );
+}
+#[test]
+fn share_a_container_between_handles() {
+    let container = Registry::default();
+    let handle = container.clone();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+
+    let service = handle
+        .get_service::<dyn SimpleService>(&SimpleSession::default())
+        .unwrap();
+    assert_eq!(service.write().unwrap().bar(), 0);
+    let service = container
+        .get_service::<dyn SimpleService>(&SimpleSession::default())
+        .unwrap();
+    assert_eq!(service.write().unwrap().bar(), 1);
+}
+#[test]
+fn drop_a_container() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let service = container
+        .get_service::<dyn SimpleService>(&SimpleSession::new())
+        .unwrap();
+    drop(container);
+
+    // the service outlives its container, but it is no longer available
+    assert_eq!(service.read().is_ok(), true);
+    assert_eq!(
+        Registry::default()
+            .get_service::<dyn SimpleService>(&SimpleSession::default())
+            .err(),
+        Some(Error::new(ErrorCode::UnregisteredService, ""))
+    );
+}
+fn bar_of(container: &Registry, session: &SimpleSession) -> u32 {
+    let service = container.get_service::<dyn SimpleService>(session).unwrap();
+    let mut service = service.write().unwrap();
+    service.bar()
+}
+#[test]
+fn share_a_singleton_service_between_sessions() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Singleton)
+        .unwrap();
+    let first = SimpleSession::new();
+    let second = SimpleSession::new();
+
+    assert_eq!(bar_of(&container, &first), 0);
+    assert_eq!(bar_of(&container, &second), 1);
+    container.clear_session(&first).unwrap();
+    assert_eq!(bar_of(&container, &first), 2);
+}
+#[test]
+fn create_a_scoped_service_per_session() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let first = SimpleSession::new();
+    let second = SimpleSession::new();
+
+    assert_eq!(bar_of(&container, &first), 0);
+    assert_eq!(bar_of(&container, &first), 1);
+    assert_eq!(bar_of(&container, &second), 0);
+    container.clear_session(&first).unwrap();
+    assert_eq!(bar_of(&container, &first), 0);
+    assert_eq!(bar_of(&container, &second), 1);
+}
+#[test]
+fn create_a_transient_service_per_call() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Transient)
+        .unwrap();
+    let session = SimpleSession::new();
+
+    assert_eq!(bar_of(&container, &session), 0);
+    assert_eq!(bar_of(&container, &session), 0);
+}
+#[test]
+fn inject_a_service_into_a_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn DependentService>(DependentServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let session = SimpleSession::new();
+
+    assert_eq!(bar_of(&container, &session), 0);
+    let service = container
+        .get_service::<dyn DependentService>(&session)
+        .unwrap();
+    assert_eq!(service.write().unwrap().bar(), 1);
+    assert_eq!(bar_of(&container, &session), 2);
+}
+#[test]
+fn inject_an_unregistered_service_into_a_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn DependentService>(DependentServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(
+        container
+            .get_service::<dyn DependentService>(&SimpleSession::default())
+            .err(),
+        Some(Error::new(ErrorCode::UnregisteredService, ""))
+    );
+}
+#[test]
+fn detect_a_dependency_cycle() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn CyclicService>(CyclicServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn OtherCyclicService>(
+            OtherCyclicServiceImpl::factory,
+            Lifetime::Singleton,
+        )
+        .unwrap();
+    assert_eq!(
+        container
+            .get_service::<dyn CyclicService>(&SimpleSession::default())
+            .err(),
+        Some(Error::new(ErrorCode::DependencyCycle, ""))
+    );
+}
+#[test]
+fn reject_a_dependency_with_a_shorter_lifetime() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn DependentService>(
+            DependentServiceImpl::factory,
+            Lifetime::Singleton,
+        )
+        .unwrap();
+    // the singleton would keep the scoped service of the first session
+    assert_eq!(
+        container
+            .get_service::<dyn DependentService>(&SimpleSession::new())
+            .err(),
+        Some(Error::new(ErrorCode::LifetimeMismatch, ""))
+    );
+
+    container
+        .unregister_service::<dyn DependentService>()
+        .unwrap();
+    container
+        .register_service::<dyn DependentService>(
+            DependentServiceImpl::factory,
+            Lifetime::Transient,
+        )
+        .unwrap();
+    assert!(container
+        .get_service::<dyn DependentService>(&SimpleSession::new())
+        .is_ok());
+}
+#[test]
+fn build_a_service_once_for_concurrent_resolvers() {
+    static BUILT: AtomicU32 = AtomicU32::new(0);
+    fn slow_factory(resolver: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        BUILT.fetch_add(1, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(100));
+        SimpleServiceImpl::factory(resolver)
+    }
+
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(slow_factory, Lifetime::Singleton)
+        .unwrap();
+
+    let other = container.clone();
+    let t = thread::spawn(move || {
+        other
+            .get_service::<dyn SimpleService>(&SimpleSession::new())
+            .unwrap()
+    });
+    let service = container
+        .get_service::<dyn SimpleService>(&SimpleSession::new())
+        .unwrap();
+    let other_service = t.join().unwrap();
+
+    assert_eq!(BUILT.load(Ordering::SeqCst), 1);
+    assert!(service.ptr_eq(&other_service));
+}
+fn foo_of(service: &ServiceHandle<dyn SimpleService>) -> bool {
+    service.read().unwrap().foo()
+}
+#[test]
+fn register_named_services() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    assert_eq!(
+        container.register_named_service::<dyn SimpleService>(
+            "replica",
+            AnotherSimpleServiceImpl::factory,
+            Lifetime::Scoped
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        container.register_named_service::<dyn SimpleService>(
+            "replica",
+            SimpleServiceImpl::factory,
+            Lifetime::Scoped
+        ),
+        Err(Error::new(ErrorCode::AlreadyRegisteredService, ""))
+    );
+
+    let session = SimpleSession::new();
+    let primary = container
+        .get_service::<dyn SimpleService>(&session)
+        .unwrap();
+    let replica = container
+        .get_named_service::<dyn SimpleService>(&session, "replica")
+        .unwrap();
+    assert!(foo_of(&primary));
+    assert!(!foo_of(&replica));
+    assert_eq!(
+        container
+            .get_named_service::<dyn SimpleService>(&session, "missing")
+            .err(),
+        Some(Error::new(ErrorCode::UnregisteredService, ""))
+    );
+
+    container
+        .unregister_named_service::<dyn SimpleService>("replica")
+        .unwrap();
+    assert_eq!(
+        container
+            .get_named_service::<dyn SimpleService>(&session, "replica")
+            .err(),
+        Some(Error::new(ErrorCode::UnregisteredService, ""))
+    );
+    assert!(container.get_service::<dyn SimpleService>(&session).is_ok());
+}
+#[test]
+fn get_all_implementations_of_a_service() {
+    let container = Registry::default();
+    let session = SimpleSession::new();
+    assert_eq!(
+        container
+            .get_services::<dyn SimpleService>(&session)
+            .map(|services| services.len()),
+        Ok(0)
+    );
+
+    container
+        .register_named_service::<dyn SimpleService>(
+            "first",
+            AnotherSimpleServiceImpl::factory,
+            Lifetime::Transient,
+        )
+        .unwrap();
+    container
+        .register_named_service::<dyn SimpleService>(
+            "second",
+            SimpleServiceImpl::factory,
+            Lifetime::Singleton,
+        )
+        .unwrap();
+    container
+        .register_service::<dyn SimpleService>(AnotherSimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+
+    let services = container
+        .get_services::<dyn SimpleService>(&session)
+        .unwrap();
+    assert_eq!(
+        services.iter().map(foo_of).collect::<Vec<_>>(),
+        vec![false, true, false]
+    );
+
+    container
+        .unregister_named_service::<dyn SimpleService>("first")
+        .unwrap();
+    let services = container
+        .get_services::<dyn SimpleService>(&session)
+        .unwrap();
+    assert_eq!(
+        services.iter().map(foo_of).collect::<Vec<_>>(),
+        vec![true, false]
+    );
+}
+#[test]
+fn read_a_service_concurrently() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Singleton)
+        .unwrap();
+    let service = container
+        .get_service::<dyn SimpleService>(&SimpleSession::new())
+        .unwrap();
+
+    let first = service.read().unwrap();
+    let second = service.read().unwrap();
+    assert!(first.foo() && second.foo());
+}
+#[test]
+fn get_a_poisoned_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn SimpleService>(SimpleServiceImpl::factory, Lifetime::Singleton)
+        .unwrap();
+    let service = container
+        .get_service::<dyn SimpleService>(&SimpleSession::new())
+        .unwrap();
+
+    let poisoned = service.clone();
+    thread::spawn(move || {
+        let _service = poisoned.write().unwrap();
+        panic!("poison the service");
+    })
+    .join()
+    .ok();
+
+    assert_eq!(
+        service.read().err(),
+        Some(Error::new(ErrorCode::ServiceError, ""))
+    );
+    assert_eq!(
+        service.write().err(),
+        Some(Error::new(ErrorCode::ServiceError, ""))
+    );
+}
+#[test]
+fn get_an_unimplemented_service() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn DependentService>(SimpleServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let service = container
+        .get_service::<dyn DependentService>(&SimpleSession::new())
+        .unwrap();
+
+    assert_eq!(
+        service.read().err(),
+        Some(Error::new(ErrorCode::Unimplemented, ""))
+    );
+}
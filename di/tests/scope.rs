@@ -0,0 +1,204 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use di::registry::{ErrorCode, Registry, Resolver, Session};
+use di::service::{Lifetime, Service};
+
+use error::Error;
+
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+trait UserService: Service {}
+#[derive(Castable)]
+#[Traits(UserService)]
+struct UserServiceImpl {}
+impl UserServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(UserServiceImpl {}))
+    }
+}
+impl UserService for UserServiceImpl {}
+impl Service for UserServiceImpl {}
+
+trait RequestService: Service {}
+#[derive(Castable)]
+#[Traits(RequestService)]
+struct RequestServiceImpl {}
+impl RequestServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(RequestServiceImpl {}))
+    }
+}
+impl RequestService for RequestServiceImpl {}
+impl Service for RequestServiceImpl {}
+
+fn registry() -> Registry {
+    let container = Registry::default();
+    container
+        .register_service::<dyn UserService>(UserServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    container
+        .register_service::<dyn RequestService>(RequestServiceImpl::factory, Lifetime::ChildScoped)
+        .unwrap();
+    container
+}
+
+#[test]
+fn share_scoped_services_with_child_scopes() {
+    let container = registry();
+    let user = container.scope();
+    let request = user.child();
+    let other_request = user.child();
+
+    let service = container.get_service::<dyn UserService>(&user).unwrap();
+    let requested = container.get_service::<dyn UserService>(&request).unwrap();
+    let other_requested = container
+        .get_service::<dyn UserService>(&other_request)
+        .unwrap();
+    assert!(service.ptr_eq(&requested));
+    assert!(service.ptr_eq(&other_requested));
+
+    let other_user = container.scope();
+    let other_service = container
+        .get_service::<dyn UserService>(&other_user.child())
+        .unwrap();
+    assert!(!service.ptr_eq(&other_service));
+}
+
+#[test]
+fn keep_child_scoped_services_in_the_child_scope() {
+    let container = registry();
+    let user = container.scope();
+    let request = user.child();
+    let other_request = user.child();
+
+    let service = container
+        .get_service::<dyn RequestService>(&request)
+        .unwrap();
+    let again = container
+        .get_service::<dyn RequestService>(&request)
+        .unwrap();
+    let other_service = container
+        .get_service::<dyn RequestService>(&other_request)
+        .unwrap();
+    let user_service = container.get_service::<dyn RequestService>(&user).unwrap();
+    assert!(service.ptr_eq(&again));
+    assert!(!service.ptr_eq(&other_service));
+    assert!(!service.ptr_eq(&user_service));
+}
+
+#[test]
+fn return_the_parent_of_a_child_scope() {
+    let container = registry();
+    let user = container.scope();
+    let request = user.child();
+
+    assert!(user.parent().is_none());
+    assert_eq!(request.parent().unwrap().key(), user.key());
+    assert_eq!(request.child().parent().unwrap().key(), request.key());
+}
+
+static UNINITIALIZED: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+trait TrackedService: Service {}
+#[derive(Castable)]
+#[Traits(TrackedService)]
+struct TrackedServiceImpl {
+    name: &'static str,
+}
+impl TrackedServiceImpl {
+    pub fn user(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(TrackedServiceImpl { name: "user" }))
+    }
+    pub fn request(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(TrackedServiceImpl { name: "request" }))
+    }
+}
+impl TrackedService for TrackedServiceImpl {}
+impl Service for TrackedServiceImpl {
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        UNINITIALIZED.lock().unwrap().push(self.name);
+        Ok(())
+    }
+}
+
+#[test]
+fn clear_a_scope_when_it_is_dropped() {
+    let container = Registry::default();
+    container
+        .register_named_service::<dyn TrackedService>(
+            "user",
+            TrackedServiceImpl::user,
+            Lifetime::Scoped,
+        )
+        .unwrap();
+    container
+        .register_named_service::<dyn TrackedService>(
+            "request",
+            TrackedServiceImpl::request,
+            Lifetime::ChildScoped,
+        )
+        .unwrap();
+    let user = container.scope();
+    let request = user.child();
+    container
+        .get_named_service::<dyn TrackedService>(&request, "user")
+        .unwrap();
+    container
+        .get_named_service::<dyn TrackedService>(&request, "request")
+        .unwrap();
+
+    drop(request);
+    assert_eq!(*UNINITIALIZED.lock().unwrap(), vec!["request"]);
+    drop(user);
+    assert_eq!(*UNINITIALIZED.lock().unwrap(), vec!["request", "user"]);
+}
+
+#[test]
+fn keep_a_parent_scope_alive_for_its_children() {
+    let container = registry();
+    let user = container.scope();
+    let request = user.child();
+    let service = container.get_service::<dyn UserService>(&user).unwrap();
+
+    drop(user);
+    let requested = container.get_service::<dyn UserService>(&request).unwrap();
+    assert!(service.ptr_eq(&requested));
+}
+
+static BUSY_UNINITIALIZED: AtomicBool = AtomicBool::new(false);
+
+trait BusyService: Service {}
+#[derive(Castable)]
+#[Traits(BusyService)]
+struct BusyServiceImpl {}
+impl BusyServiceImpl {
+    pub fn factory(_: &Resolver) -> Result<Box<dyn Castable + Sync>, Error<ErrorCode>> {
+        Ok(Box::new(BusyServiceImpl {}))
+    }
+}
+impl BusyService for BusyServiceImpl {}
+impl Service for BusyServiceImpl {
+    fn uninitialize(&mut self) -> Result<(), Error<ErrorCode>> {
+        BUSY_UNINITIALIZED.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[test]
+fn skip_services_in_use_when_a_scope_is_dropped() {
+    let container = Registry::default();
+    container
+        .register_service::<dyn BusyService>(BusyServiceImpl::factory, Lifetime::Scoped)
+        .unwrap();
+    let scope = container.scope();
+    let handle = container.get_service::<dyn BusyService>(&scope).unwrap();
+    let service = handle.read().unwrap();
+
+    // waiting for the lock held by this thread would never return
+    drop(scope);
+    drop(service);
+    assert!(!BUSY_UNINITIALIZED.load(Ordering::SeqCst));
+    assert!(container.introspect().unwrap().sessions.is_empty());
+}
@@ -0,0 +1,23 @@
+use proc_macro::{self, TokenStream};
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput};
+
+/// Implements eventbus::event::Event for a struct or enum. The event must be
+/// Castable as well, so it is usually derived together with
+/// #[derive(Castable, Event)].
+#[proc_macro_derive(Event)]
+pub fn derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let event_type = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let output = quote! {
+        impl #impl_generics eventbus::event::Event for #event_type #type_generics #where_clause {
+            fn type_name(&self) -> &'static str {
+                ::std::any::type_name::<Self>()
+            }
+        }
+    };
+
+    output.into()
+}
@@ -0,0 +1,53 @@
+use std::any::TypeId;
+
+use eventbus::event::Event;
+use eventbus_derive::Event;
+use traitcast_derive::Castable;
+
+trait Described {
+    fn describe(&self) -> String;
+}
+
+#[derive(Castable, Event)]
+struct SimpleEvent {
+    value: u32,
+}
+
+#[derive(Castable, Event)]
+#[Traits(Described)]
+struct DescribedEvent {}
+impl Described for DescribedEvent {
+    fn describe(&self) -> String {
+        String::from("described")
+    }
+}
+
+#[test]
+fn event_type_name() {
+    let event: Box<dyn Event> = Box::new(SimpleEvent { value: 1 });
+    assert!(event.type_name().ends_with("SimpleEvent"));
+}
+#[test]
+fn event_type_id() {
+    let event: Box<dyn Event> = Box::new(SimpleEvent { value: 1 });
+    assert_eq!(event.event_type_id(), TypeId::of::<SimpleEvent>());
+    assert!(event.is::<SimpleEvent>());
+    assert!(!event.is::<DescribedEvent>());
+}
+#[test]
+fn downcast_event() {
+    let mut event: Box<dyn Event> = Box::new(SimpleEvent { value: 1 });
+    assert_eq!(event.downcast_ref::<SimpleEvent>().unwrap().value, 1);
+    assert!(event.downcast_ref::<DescribedEvent>().is_none());
+
+    event.downcast_mut::<SimpleEvent>().unwrap().value = 2;
+    assert_eq!(event.downcast_ref::<SimpleEvent>().unwrap().value, 2);
+}
+#[test]
+fn cast_event_to_trait() {
+    let event: Box<dyn Event> = Box::new(DescribedEvent {});
+    let castable: &dyn traitcast::Castable = event.as_ref();
+    let described = castable.query_ref::<dyn Described>();
+    assert!(described.is_some());
+    assert_eq!(described.unwrap().describe(), "described");
+}
@@ -0,0 +1,215 @@
+use error::Error;
+use log::{debug, error};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use traitcast_derive::Castable;
+use websocket::{OnTextMessageSubscription, TextSender};
+
+use crate::event::{
+    ErrorCode, Event, EventBus, EventBusSubscriptions, Origin, Propagation, Subscriber,
+    Subscription, SubscriptionOptions, SubscriptionRegistry,
+};
+
+///////////////////////////////////////////////////////////////////////////////
+/// Wire format of the bridge: combines the name of an event and its encoded
+/// payload to one text message and splits a received message again
+pub trait Serializer: Send + Sync {
+    fn serialize(&self, name: &str, payload: &str) -> String;
+    fn deserialize(&self, message: &str) -> Result<(String, String), Error<ErrorCode>>;
+}
+
+/// Puts the name in the first line and the payload in the rest of the message
+pub struct LineSerializer;
+impl Serializer for LineSerializer {
+    fn serialize(&self, name: &str, payload: &str) -> String {
+        format!("{name}\n{payload}")
+    }
+
+    fn deserialize(&self, message: &str) -> Result<(String, String), Error<ErrorCode>> {
+        message
+            .split_once('\n')
+            .map(|(name, payload)| (name.to_string(), payload.to_string()))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorCode::CouldNotDecodeEvent,
+                    format!("Message without event name: {message}").as_str(),
+                )
+            })
+    }
+}
+
+/// Encodes the payload of a forwarded event
+pub type Encoder<T> = fn(&T) -> String;
+/// Decodes the payload of a received event, None if it is malformed
+pub type Decoder<T> = fn(&str) -> Option<T>;
+
+type AnyDecoder = Box<dyn Fn(&str) -> Option<Box<dyn Event>> + Send>;
+
+///////////////////////////////////////////////////////////////////////////////
+/// State the bridge shares with its forwarding subscribers
+struct Shared {
+    serializer: Box<dyn Serializer>,
+    sender: Arc<Mutex<dyn TextSender + Send>>,
+    /// origin of the received events, which must not be sent back to the peer
+    origin: Origin,
+}
+impl Shared {
+    fn send(&self, name: &str, payload: &str) -> Result<(), Error<ErrorCode>> {
+        let message = self.serializer.serialize(name, payload);
+        let mut sender = self.sender.lock().map_err(|err| {
+            Error::new(
+                ErrorCode::CouldNotForwardEvent,
+                format!("Could not lock sender ({err})").as_str(),
+            )
+        })?;
+        sender.send(message.as_str()).map_err(|err| {
+            Error::new(
+                ErrorCode::CouldNotForwardEvent,
+                format!("Could not send {name} ({})", err.message).as_str(),
+            )
+        })
+    }
+}
+
+/// Sends the events of type T to the peer
+struct Forwarder<T: Event> {
+    name: String,
+    encode: Encoder<T>,
+    shared: Arc<Shared>,
+}
+impl<T: Event> Subscriber<T> for Forwarder<T> {
+    fn notify(&mut self, event: &T) -> Propagation {
+        if Origin::current() == Some(self.shared.origin) {
+            return Propagation::Continue;
+        }
+        debug!(target: "eventbus", "Forwarding event {}", self.name);
+        if let Err(err) = self
+            .shared
+            .send(self.name.as_str(), (self.encode)(event).as_str())
+        {
+            error!(target: "eventbus", "{}", err.message);
+        }
+        Propagation::Continue
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Connects a local event bus with a remote peer over a web socket.
+///
+/// Forwarded events are encoded and sent with the TextSender. Received text
+/// messages are decoded by the decoder registered for the event name and
+/// triggered on the local bus with the origin of the bridge. Subscribe the
+/// bridge to the web socket to receive messages. A received event is not
+/// forwarded back to the peer, so the bus must support
+/// EventBus::trigger_event_from.
+#[derive(Castable)]
+#[Traits(OnTextMessageSubscription)]
+pub struct EventBridge {
+    shared: Arc<Shared>,
+    event_bus: Arc<Mutex<dyn EventBus + Send>>,
+    subscriptions: EventBusSubscriptions,
+    decoders: HashMap<String, AnyDecoder>,
+    /// the bus only keeps weak references to the forwarders
+    forwarders: HashMap<TypeId, (Box<dyn Any + Send>, Subscription)>,
+}
+impl EventBridge {
+    pub fn new(
+        event_bus: Arc<Mutex<dyn EventBus + Send>>,
+        subscriptions: EventBusSubscriptions,
+        sender: Arc<Mutex<dyn TextSender + Send>>,
+        serializer: Box<dyn Serializer>,
+    ) -> EventBridge {
+        EventBridge {
+            shared: Arc::new(Shared {
+                serializer,
+                sender,
+                origin: Origin::new(),
+            }),
+            event_bus,
+            subscriptions,
+            decoders: HashMap::new(),
+            forwarders: HashMap::new(),
+        }
+    }
+
+    /// Sends every event of type T triggered on the local bus to the peer
+    pub fn forward<T: Event>(
+        &mut self,
+        name: &str,
+        encode: Encoder<T>,
+    ) -> Result<(), Error<ErrorCode>> {
+        if self.forwarders.contains_key(&TypeId::of::<T>()) {
+            return Err(Error::new(
+                ErrorCode::AlreadySubscribed,
+                format!("Event {name} is already forwarded").as_str(),
+            ));
+        }
+        let forwarder: Arc<Mutex<Box<dyn Subscriber<T>>>> =
+            Arc::new(Mutex::new(Box::new(Forwarder {
+                name: name.to_string(),
+                encode,
+                shared: self.shared.clone(),
+            })));
+        // first in line, so a local subscriber consuming the event does not
+        // keep it from the peer
+        let subscription = self.subscriptions.subscribe_event_with(
+            &forwarder,
+            SubscriptionOptions::default().with_priority(i32::MAX),
+        )?;
+        self.forwarders
+            .insert(TypeId::of::<T>(), (Box::new(forwarder), subscription));
+        Ok(())
+    }
+
+    /// Triggers the events of the peer named name on the local bus
+    pub fn receive<T: Event>(
+        &mut self,
+        name: &str,
+        decode: Decoder<T>,
+    ) -> Result<(), Error<ErrorCode>> {
+        if self.decoders.contains_key(name) {
+            return Err(Error::new(
+                ErrorCode::AlreadySubscribed,
+                format!("Event {name} is already received").as_str(),
+            ));
+        }
+        let decoder: AnyDecoder =
+            Box::new(move |payload| decode(payload).map(|event| Box::new(event) as Box<dyn Event>));
+        self.decoders.insert(name.to_string(), decoder);
+        Ok(())
+    }
+
+    fn inject(&self, message: &str) -> Result<(), Error<ErrorCode>> {
+        let (name, payload) = self.shared.serializer.deserialize(message)?;
+        let decode = self.decoders.get(&name).ok_or_else(|| {
+            Error::new(
+                ErrorCode::CouldNotDecodeEvent,
+                format!("No decoder for event {name}").as_str(),
+            )
+        })?;
+        let event = decode(payload.as_str()).ok_or_else(|| {
+            Error::new(
+                ErrorCode::CouldNotDecodeEvent,
+                format!("Could not decode event {name}").as_str(),
+            )
+        })?;
+
+        self.event_bus
+            .lock()
+            .map_err(|err| {
+                Error::new(
+                    ErrorCode::CouldNotQueueEvent,
+                    format!("Could not lock event bus ({err})").as_str(),
+                )
+            })
+            .and_then(|mut event_bus| event_bus.trigger_event_from(event, self.shared.origin))
+    }
+}
+impl OnTextMessageSubscription for EventBridge {
+    fn on_message(&mut self, message: &str) {
+        if let Err(err) = self.inject(message) {
+            error!(target: "eventbus", "{}", err.message);
+        }
+    }
+}
@@ -1,43 +1,869 @@
 use error::Error;
-use std::sync::{Arc, Mutex};
+use log::error;
+use std::any::{Any, TypeId};
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use traitcast::Castable;
 
-///
+use crate::request::{
+    self, PendingResponse, Request, RequestBus, Respond, Responder, ResponderEntry,
+};
+
+/// Error codes of the event bus
+#[derive(PartialEq, Debug)]
 pub enum ErrorCode {
     NotImplemented,
+    AlreadySubscribed,
+    NotSubscribed,
+    CouldNotSubscribe,
+    CouldNotNotifySubscriber,
+    CouldNotQueueEvent,
+    QueueFull,
+    ShutDown,
+    NoResponder,
+    MultipleResponders,
+    Timeout,
+    CouldNotForwardEvent,
+    CouldNotDecodeEvent,
 }
 
-///
+/// Anything events can be triggered on
 pub trait EventBus {
     fn trigger_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>>;
+
+    /// Triggers an event the bus keeps until the next sticky event of the same
+    /// type replaces it. Subscribers subscribing later get it right away, so
+    /// they know the current state, e.g. whether a connection is open.
+    fn trigger_sticky_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>> {
+        Err(Error::new(
+            ErrorCode::NotImplemented,
+            format!("Sticky event {} not supported", event.type_name()).as_str(),
+        ))
+    }
+
+    /// Triggers an event that came from the given origin rather than from
+    /// this application, e.g. an event received by an EventBridge. The
+    /// subscribers get the origin from Origin::current() while they are
+    /// notified.
+    fn trigger_event_from(
+        &mut self,
+        event: Box<dyn Event>,
+        _origin: Origin,
+    ) -> Result<(), Error<ErrorCode>> {
+        Err(Error::new(
+            ErrorCode::NotImplemented,
+            format!(
+                "Event {} from another origin not supported",
+                event.type_name()
+            )
+            .as_str(),
+        ))
+    }
 }
+/// Registration of subscribers. A subscriber either subscribes to a concrete
+/// event type, to a trait that a family of events implements (the event must
+/// list the trait in #[Traits(...)]), or to all events.
+///
+/// Matching subscribers are notified by descending priority, subscribers with
+/// the same priority in the order they subscribed.
 ///
-trait SubscriptionRegistry {
+/// The bus only keeps a weak reference to a subscriber. A subscription ends
+/// when the returned Subscription is dropped or the subscriber is dropped.
+///
+/// A new subscriber immediately gets the sticky events it accepts and, if it
+/// asks for a replay, the events in the history of the bus.
+pub trait SubscriptionRegistry {
+    fn subscribe_event_with<T: Event>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>>;
+
     fn subscribe_event<T: Event>(
         &mut self,
-        subscriber: Arc<Mutex<Box<dyn Subscriber<T>>>>,
-    ) -> Result<(), Error<ErrorCode>>;
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscribe_event_with(subscriber, SubscriptionOptions::default())
+    }
+
+    fn subscribe_trait_with<T: ?Sized + 'static>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>>;
+
+    fn subscribe_trait<T: ?Sized + 'static>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscribe_trait_with(subscriber, SubscriptionOptions::default())
+    }
+
+    fn subscribe_all_with(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<dyn Event>>>>,
+        options: SubscriptionOptions<dyn Event>,
+    ) -> Result<Subscription, Error<ErrorCode>>;
+
+    fn subscribe_all(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<dyn Event>>>>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscribe_all_with(subscriber, SubscriptionOptions::default())
+    }
 
-    fn unsubscribe_event<T: Event>(
+    /// Registers the responder of the requests of type R. A request type has
+    /// at most one responder, a second one fails with
+    /// ErrorCode::MultipleResponders.
+    fn respond_to<R: Request>(
         &mut self,
-        subscriber: Arc<Mutex<Box<dyn Subscriber<T>>>>,
-    ) -> Result<(), Error<ErrorCode>>;
+        responder: &Arc<Mutex<Box<dyn Responder<R>>>>,
+    ) -> Result<Subscription, Error<ErrorCode>>;
 }
-///
-trait Subscriber<T: Event> {
-    fn notify(&mut self, event: &T);
+/// A subscriber gets notified about every event of type T. T is either a
+/// concrete event, a trait of an event family or dyn Event.
+pub trait Subscriber<T: ?Sized>: Send {
+    fn notify(&mut self, event: &T) -> Propagation;
+}
+/// Returned by a subscriber to tell the bus whether subscribers with a lower
+/// priority still get the event
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Propagation {
+    Continue,
+    /// The event is handled, later subscribers skip it
+    Consumed,
+}
+/// Predicate deciding whether a subscriber gets an event
+pub type Filter<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
+/// Priority and filter of a subscription
+pub struct SubscriptionOptions<T: ?Sized> {
+    /// Subscribers with a higher priority are notified first (default 0)
+    pub priority: i32,
+    /// The subscriber only gets the events this predicate accepts
+    pub filter: Option<Filter<T>>,
+    /// The subscriber gets the history of the bus when it subscribes
+    pub replay: bool,
+}
+impl<T: ?Sized> SubscriptionOptions<T> {
+    pub fn with_priority(mut self, priority: i32) -> SubscriptionOptions<T> {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_filter(
+        mut self,
+        filter: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> SubscriptionOptions<T> {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    pub fn with_replay(mut self, replay: bool) -> SubscriptionOptions<T> {
+        self.replay = replay;
+        self
+    }
+}
+impl<T: ?Sized> Default for SubscriptionOptions<T> {
+    fn default() -> Self {
+        SubscriptionOptions {
+            priority: 0,
+            filter: None,
+            replay: false,
+        }
+    }
 }
-/// An Event has to copy all dataNotImplemented
+/// Token of a subscription. Dropping it unsubscribes the subscriber.
+#[must_use = "dropping a Subscription unsubscribes immediately"]
+pub struct Subscription {
+    subscribers: Weak<Mutex<Subscribers>>,
+    id: u64,
+}
+impl Subscription {
+    pub(crate) fn new(subscribers: &Arc<Mutex<Subscribers>>, id: u64) -> Subscription {
+        Subscription {
+            subscribers: Arc::downgrade(subscribers),
+            id,
+        }
+    }
+
+    /// Unsubscribes explicitly, which reports subscriptions that already ended
+    /// because the bus or the subscriber was dropped
+    pub fn unsubscribe(mut self) -> Result<(), Error<ErrorCode>> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Result<(), Error<ErrorCode>> {
+        let subscribers = std::mem::take(&mut self.subscribers)
+            .upgrade()
+            .ok_or_else(|| Error::new(ErrorCode::NotSubscribed, "Event bus no longer exists"))?;
+        let result = lock_subscribers(&subscribers)?.remove(self.id);
+        result
+    }
+}
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.remove().ok();
+    }
+}
+/// An Event has to copy all data
 ///
-pub trait Event {}
+/// Events are Castable, so a subscriber can ask for any trait the event
+/// implements. Use #[derive(Castable, Event)] to implement both. Events are
+/// Sync, because sticky events and the history are shared between threads.
+pub trait Event: Castable + Any + Sync {
+    /// Name of the concrete event type
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+/// Type information of a boxed event
+impl dyn Event {
+    /// TypeId of the concrete event type (not of the trait object)
+    pub fn event_type_id(&self) -> TypeId {
+        let any: &dyn Any = self;
+        any.type_id()
+    }
+
+    pub fn is<T: Event>(&self) -> bool {
+        self.event_type_id() == TypeId::of::<T>()
+    }
+
+    pub fn downcast_ref<T: Event>(&self) -> Option<&T> {
+        let any: &dyn Any = self;
+        any.downcast_ref::<T>()
+    }
+
+    pub fn downcast_mut<T: Event>(&mut self) -> Option<&mut T> {
+        let any: &mut dyn Any = self;
+        any.downcast_mut::<T>()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Where an event triggered with EventBus::trigger_event_from came from. Every
+/// new Origin differs from all others.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct Origin(u64);
+
+static NEXT_ORIGIN: AtomicU64 = AtomicU64::new(0);
+thread_local! {
+    /// origin of the event the thread is delivering
+    static DELIVERING: Cell<Option<Origin>> = const { Cell::new(None) };
+}
+
+impl Origin {
+    pub fn new() -> Origin {
+        Origin(NEXT_ORIGIN.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Origin of the event the current thread notifies its subscribers of,
+    /// None if the event was triggered locally
+    pub fn current() -> Option<Origin> {
+        DELIVERING.with(Cell::get)
+    }
+
+    /// Runs deliver with origin as the current origin. Events triggered by
+    /// subscribers meanwhile are delivered with their own origin.
+    pub(crate) fn delivering<R>(origin: Option<Origin>, deliver: impl FnOnce() -> R) -> R {
+        /// Restores the origin of the outer delivery, even if a subscriber
+        /// panics
+        struct Restore(Option<Origin>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                DELIVERING.with(|current| current.set(self.0));
+            }
+        }
+        let _restore = Restore(DELIVERING.with(|current| current.replace(origin)));
+        deliver()
+    }
+}
+impl Default for Origin {
+    fn default() -> Self {
+        Origin::new()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Type erased subscriber, so subscribers of different event types can be
+/// stored in the same map
+pub(crate) trait SubscriberEntry: Send + Sync {
+    /// Ids grow with every subscription, so they keep the subscription order
+    fn id(&self) -> u64;
+    fn priority(&self) -> i32;
+    fn replay(&self) -> bool;
+    fn is_alive(&self) -> bool;
+    fn accepts(&self, event: &dyn Event) -> bool;
+    fn notify(&self, event: &dyn Event) -> Result<Propagation, Error<ErrorCode>>;
+    fn as_any(&self) -> &dyn Any;
+}
+/// A subscriber together with the cast of an event to what it subscribed to
+struct Entry<T: ?Sized + 'static> {
+    id: u64,
+    subscriber: Weak<Mutex<Box<dyn Subscriber<T>>>>,
+    cast: fn(&dyn Event) -> Option<&T>,
+    options: SubscriptionOptions<T>,
+}
+impl<T: ?Sized + 'static> SubscriberEntry for Entry<T> {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn priority(&self) -> i32 {
+        self.options.priority
+    }
+
+    fn replay(&self) -> bool {
+        self.options.replay
+    }
+
+    fn is_alive(&self) -> bool {
+        self.subscriber.strong_count() > 0
+    }
+
+    fn accepts(&self, event: &dyn Event) -> bool {
+        match ((self.cast)(event), &self.options.filter) {
+            (Some(casted), Some(filter)) => filter(casted),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn notify(&self, event: &dyn Event) -> Result<Propagation, Error<ErrorCode>> {
+        // the subscriber may have been dropped since the snapshot was taken
+        let subscriber = match self.subscriber.upgrade() {
+            Some(subscriber) => subscriber,
+            None => return Ok(Propagation::Continue),
+        };
+        let casted = (self.cast)(event).ok_or_else(|| {
+            Error::new(
+                ErrorCode::CouldNotNotifySubscriber,
+                format!(
+                    "Event {} is no {}",
+                    event.type_name(),
+                    std::any::type_name::<T>()
+                )
+                .as_str(),
+            )
+        })?;
+        let propagation = subscriber
+            .lock()
+            .map_err(|err| {
+                Error::new(
+                    ErrorCode::CouldNotNotifySubscriber,
+                    format!("Could not notify subscriber ({err})").as_str(),
+                )
+            })?
+            .notify(casted);
+        Ok(propagation)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        &self.subscriber
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// A delivered event with its position in the order of all delivered events
+#[derive(Clone)]
+struct Recorded {
+    sequence: u64,
+    event: Arc<dyn Event>,
+    origin: Option<Origin>,
+}
+
+/// Sticky and historic events a new subscriber gets. Only events delivered
+/// before the subscription are replayed, later ones reach it as usual.
+pub(crate) struct Replay {
+    entry: Arc<dyn SubscriberEntry>,
+    before: u64,
+}
+impl Replay {
+    pub(crate) fn deliver(&self, subscribers: &Mutex<Subscribers>) {
+        let events = match lock_subscribers(subscribers) {
+            Ok(subscribers) => subscribers.replayed_to(self),
+            Err(err) => {
+                error!(target: "eventbus", "{}", err.message);
+                return;
+            }
+        };
+        for recorded in events {
+            let notified = Origin::delivering(recorded.origin, || {
+                self.entry.notify(recorded.event.as_ref())
+            });
+            if let Err(err) = notified {
+                error!(target: "eventbus", "{}", err.message);
+            }
+        }
+    }
+}
+
+/// Delivers the replay of a new subscription the way the bus delivers events
+pub(crate) trait Dispatcher: Send + Sync {
+    fn replay(&self, subscribers: &Arc<Mutex<Subscribers>>, replay: Replay);
+}
+/// Delivers the replay on the subscribing thread
+struct Immediate;
+impl Dispatcher for Immediate {
+    fn replay(&self, subscribers: &Arc<Mutex<Subscribers>>, replay: Replay) {
+        replay.deliver(subscribers);
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// The subscribers of a bus together with its sticky events and history. It
+/// is shared by all bus implementations of this crate and by the Subscription
+/// tokens.
+pub(crate) struct Subscribers {
+    /// subscribers of concrete event types
+    events: HashMap<TypeId, Vec<Arc<dyn SubscriberEntry>>>,
+    /// subscribers of traits and of all events
+    families: Vec<Arc<dyn SubscriberEntry>>,
+    /// the responder of each request type
+    pub(crate) responders: HashMap<TypeId, Arc<dyn ResponderEntry>>,
+    next_id: u64,
+    /// the last sticky event per type, oldest first
+    sticky: Vec<Recorded>,
+    history: VecDeque<Recorded>,
+    history_capacity: usize,
+    next_sequence: u64,
+}
+impl Subscribers {
+    pub(crate) fn new(history_capacity: usize) -> Arc<Mutex<Subscribers>> {
+        Arc::new(Mutex::new(Subscribers {
+            events: HashMap::new(),
+            families: Vec::new(),
+            responders: HashMap::new(),
+            next_id: 0,
+            sticky: Vec::new(),
+            history: VecDeque::with_capacity(history_capacity),
+            history_capacity,
+            next_sequence: 0,
+        }))
+    }
+
+    pub(crate) fn subscribe<T: Event>(
+        subscribers: &Arc<Mutex<Subscribers>>,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<(Subscription, Replay), Error<ErrorCode>> {
+        let mut guard = lock_subscribers(subscribers)?;
+        let entry = Entry {
+            id: guard.next_id(),
+            subscriber: Arc::downgrade(subscriber),
+            cast: |event| event.downcast_ref::<T>(),
+            options,
+        };
+        let entry = Subscribers::add(guard.events.entry(TypeId::of::<T>()).or_default(), entry)?;
+        Ok(guard.subscribed(subscribers, entry))
+    }
+
+    pub(crate) fn subscribe_trait<T: ?Sized + 'static>(
+        subscribers: &Arc<Mutex<Subscribers>>,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<(Subscription, Replay), Error<ErrorCode>> {
+        let mut guard = lock_subscribers(subscribers)?;
+        let entry = Entry {
+            id: guard.next_id(),
+            subscriber: Arc::downgrade(subscriber),
+            cast: |event| {
+                let castable: &dyn Castable = event;
+                castable.query_ref::<T>()
+            },
+            options,
+        };
+        let entry = Subscribers::add(&mut guard.families, entry)?;
+        Ok(guard.subscribed(subscribers, entry))
+    }
+
+    pub(crate) fn subscribe_all(
+        subscribers: &Arc<Mutex<Subscribers>>,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<dyn Event>>>>,
+        options: SubscriptionOptions<dyn Event>,
+    ) -> Result<(Subscription, Replay), Error<ErrorCode>> {
+        let mut guard = lock_subscribers(subscribers)?;
+        let entry = Entry {
+            id: guard.next_id(),
+            subscriber: Arc::downgrade(subscriber),
+            cast: |event| Some(event),
+            options,
+        };
+        let entry = Subscribers::add(&mut guard.families, entry)?;
+        Ok(guard.subscribed(subscribers, entry))
+    }
 
-pub struct EventBusDefault {}
+    /// Returns a snapshot of the subscribers accepting an event in the order
+    /// they have to be notified, so they can be notified without holding a
+    /// lock on the subscribers. Subscribers that were dropped are removed.
+    pub(crate) fn subscribers_of(&mut self, event: &dyn Event) -> Vec<Arc<dyn SubscriberEntry>> {
+        self.events
+            .values_mut()
+            .for_each(|subscribers| subscribers.retain(|subscriber| subscriber.is_alive()));
+        self.events.retain(|_, subscribers| !subscribers.is_empty());
+        self.families.retain(|subscriber| subscriber.is_alive());
+
+        let mut subscribers: Vec<Arc<dyn SubscriberEntry>> = self
+            .events
+            .get(&event.event_type_id())
+            .into_iter()
+            .flatten()
+            .chain(self.families.iter())
+            .filter(|subscriber| subscriber.accepts(event))
+            .cloned()
+            .collect();
+        subscribers.sort_by(|a, b| b.priority().cmp(&a.priority()).then(a.id().cmp(&b.id())));
+        subscribers
+    }
+
+    /// Keeps a delivered event as sticky event and in the history
+    pub(crate) fn record(&mut self, event: &Arc<dyn Event>, sticky: bool, origin: Option<Origin>) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        if sticky {
+            let type_id = event.event_type_id();
+            self.sticky
+                .retain(|recorded| recorded.event.event_type_id() != type_id);
+            self.sticky.push(Recorded {
+                sequence,
+                event: event.clone(),
+                origin,
+            });
+        }
+        if self.history_capacity > 0 {
+            if self.history.len() == self.history_capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(Recorded {
+                sequence,
+                event: event.clone(),
+                origin,
+            });
+        }
+    }
+
+    /// Ends all subscriptions and forgets the sticky events and the history
+    pub(crate) fn clear(&mut self) {
+        self.events.clear();
+        self.families.clear();
+        self.responders.clear();
+        self.sticky.clear();
+        self.history.clear();
+    }
+
+    fn subscribed(
+        &self,
+        subscribers: &Arc<Mutex<Subscribers>>,
+        entry: Arc<dyn SubscriberEntry>,
+    ) -> (Subscription, Replay) {
+        let subscription = Subscription::new(subscribers, entry.id());
+        let replay = Replay {
+            entry,
+            before: self.next_sequence,
+        };
+        (subscription, replay)
+    }
+
+    /// Events of a replay in the order they were delivered. A sticky event
+    /// replaced after the subscription is not replayed, the subscriber already
+    /// got its successor.
+    fn replayed_to(&self, replay: &Replay) -> Vec<Recorded> {
+        let history = self.history.iter().filter(|_| replay.entry.replay());
+        let mut recorded: Vec<&Recorded> = self
+            .sticky
+            .iter()
+            .chain(history)
+            .filter(|recorded| recorded.sequence < replay.before)
+            .collect();
+        recorded.sort_by_key(|recorded| recorded.sequence);
+        recorded.dedup_by_key(|recorded| recorded.sequence);
+        recorded
+            .into_iter()
+            .filter(|recorded| replay.entry.accepts(recorded.event.as_ref()))
+            .cloned()
+            .collect()
+    }
+
+    fn remove(&mut self, id: u64) -> Result<(), Error<ErrorCode>> {
+        let before = self.len();
+        self.events
+            .values_mut()
+            .for_each(|subscribers| subscribers.retain(|subscriber| subscriber.id() != id));
+        self.events.retain(|_, subscribers| !subscribers.is_empty());
+        self.families.retain(|subscriber| subscriber.id() != id);
+        self.responders.retain(|_, responder| responder.id() != id);
+
+        if self.len() == before {
+            return Err(Error::new(
+                ErrorCode::NotSubscribed,
+                "Subscriber is not subscribed anymore",
+            ));
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.events
+            .values()
+            .map(|subscribers| subscribers.len())
+            .sum::<usize>()
+            + self.families.len()
+            + self.responders.len()
+    }
+
+    pub(crate) fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn add<T: ?Sized + 'static>(
+        subscribers: &mut Vec<Arc<dyn SubscriberEntry>>,
+        entry: Entry<T>,
+    ) -> Result<Arc<dyn SubscriberEntry>, Error<ErrorCode>> {
+        let subscribed = subscribers.iter().any(|subscriber| {
+            match subscriber
+                .as_any()
+                .downcast_ref::<Weak<Mutex<Box<dyn Subscriber<T>>>>>()
+            {
+                Some(subscriber) => subscriber.ptr_eq(&entry.subscriber),
+                None => false,
+            }
+        });
+        if subscribed {
+            return Err(Error::new(
+                ErrorCode::AlreadySubscribed,
+                format!(
+                    "Subscriber already subscribed to {}",
+                    std::any::type_name::<T>()
+                )
+                .as_str(),
+            ));
+        }
+        let entry: Arc<dyn SubscriberEntry> = Arc::new(entry);
+        subscribers.push(entry.clone());
+        Ok(entry)
+    }
+}
+
+pub(crate) fn lock_subscribers(
+    subscribers: &Mutex<Subscribers>,
+) -> Result<MutexGuard<'_, Subscribers>, Error<ErrorCode>> {
+    subscribers.lock().map_err(|err| {
+        Error::new(
+            ErrorCode::CouldNotSubscribe,
+            format!("Could not lock subscribers ({err})").as_str(),
+        )
+    })
+}
+
+/// Notifies the subscribers in the given order until one of them consumes the
+/// event. A failing subscriber must not prevent the others from being
+/// notified, so the first error is returned after all of them were called.
+pub(crate) fn notify_subscribers(
+    subscribers: &[Arc<dyn SubscriberEntry>],
+    event: &dyn Event,
+) -> Result<(), Error<ErrorCode>> {
+    let mut result = Ok(());
+    for subscriber in subscribers {
+        match subscriber.notify(event) {
+            Ok(Propagation::Consumed) => break,
+            Ok(Propagation::Continue) => (),
+            Err(err) => {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+    }
+    result
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Subscribers of a bus, detached from the bus itself. It allows subscribing
+/// where the bus is only known as a trait object, e.g. as EventBusService.
+#[derive(Clone)]
+pub struct EventBusSubscriptions {
+    subscribers: Arc<Mutex<Subscribers>>,
+    dispatcher: Arc<dyn Dispatcher>,
+}
+impl EventBusSubscriptions {
+    pub(crate) fn new(
+        subscribers: &Arc<Mutex<Subscribers>>,
+        dispatcher: Arc<dyn Dispatcher>,
+    ) -> EventBusSubscriptions {
+        EventBusSubscriptions {
+            subscribers: subscribers.clone(),
+            dispatcher,
+        }
+    }
+
+    fn replay(
+        &self,
+        subscribed: Result<(Subscription, Replay), Error<ErrorCode>>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        let (subscription, replay) = subscribed?;
+        self.dispatcher.replay(&self.subscribers, replay);
+        Ok(subscription)
+    }
+}
+impl SubscriptionRegistry for EventBusSubscriptions {
+    fn subscribe_event_with<T: Event>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.replay(Subscribers::subscribe(
+            &self.subscribers,
+            subscriber,
+            options,
+        ))
+    }
+
+    fn subscribe_trait_with<T: ?Sized + 'static>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.replay(Subscribers::subscribe_trait(
+            &self.subscribers,
+            subscriber,
+            options,
+        ))
+    }
+
+    fn subscribe_all_with(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<dyn Event>>>>,
+        options: SubscriptionOptions<dyn Event>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.replay(Subscribers::subscribe_all(
+            &self.subscribers,
+            subscriber,
+            options,
+        ))
+    }
+
+    fn respond_to<R: Request>(
+        &mut self,
+        responder: &Arc<Mutex<Box<dyn Responder<R>>>>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        request::respond_to(&self.subscribers, responder)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Synchronous event bus: an event is delivered to all of its subscribers
+/// before trigger_event returns
+pub struct EventBusDefault {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
 impl EventBusDefault {
     pub fn new() -> EventBusDefault {
-        EventBusDefault {}
+        EventBusDefault::with_history(0)
+    }
+
+    /// Creates a bus keeping the last history_capacity events for replays
+    pub fn with_history(history_capacity: usize) -> EventBusDefault {
+        EventBusDefault {
+            subscribers: Subscribers::new(history_capacity),
+        }
+    }
+
+    pub fn subscriptions(&self) -> EventBusSubscriptions {
+        EventBusSubscriptions::new(&self.subscribers, Arc::new(Immediate))
+    }
+
+    /// Ends all subscriptions of the bus
+    pub fn clear(&mut self) -> Result<(), Error<ErrorCode>> {
+        lock_subscribers(&self.subscribers)?.clear();
+        Ok(())
+    }
+
+    fn deliver(
+        &mut self,
+        event: Box<dyn Event>,
+        sticky: bool,
+        respond: Option<Respond>,
+        origin: Option<Origin>,
+    ) -> Result<(), Error<ErrorCode>> {
+        let event: Arc<dyn Event> = Arc::from(event);
+        if let Some(respond) = respond {
+            respond.respond(event.as_ref());
+        }
+        let subscribers = {
+            let mut subscribers = lock_subscribers(&self.subscribers)?;
+            subscribers.record(&event, sticky, origin);
+            subscribers.subscribers_of(event.as_ref())
+        };
+        Origin::delivering(origin, || notify_subscribers(&subscribers, event.as_ref()))
+    }
+}
+impl Default for EventBusDefault {
+    fn default() -> Self {
+        EventBusDefault::new()
     }
 }
 impl EventBus for EventBusDefault {
     fn trigger_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>> {
-        Err(Error::new(ErrorCode::NotImplemented, ""))
+        self.deliver(event, false, None, None)
+    }
+
+    fn trigger_sticky_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>> {
+        self.deliver(event, true, None, None)
+    }
+
+    fn trigger_event_from(
+        &mut self,
+        event: Box<dyn Event>,
+        origin: Origin,
+    ) -> Result<(), Error<ErrorCode>> {
+        self.deliver(event, false, None, Some(origin))
+    }
+}
+impl RequestBus for EventBusDefault {
+    fn send_request<R: Request>(
+        &mut self,
+        request: R,
+    ) -> Result<PendingResponse<R::Response>, Error<ErrorCode>> {
+        let (response, respond) = request::prepare::<R>(&self.subscribers)?;
+        self.deliver(Box::new(request), false, Some(respond), None)?;
+        Ok(response)
+    }
+}
+impl SubscriptionRegistry for EventBusDefault {
+    fn subscribe_event_with<T: Event>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions()
+            .subscribe_event_with(subscriber, options)
+    }
+
+    fn subscribe_trait_with<T: ?Sized + 'static>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions()
+            .subscribe_trait_with(subscriber, options)
+    }
+
+    fn subscribe_all_with(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<dyn Event>>>>,
+        options: SubscriptionOptions<dyn Event>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions().subscribe_all_with(subscriber, options)
+    }
+
+    fn respond_to<R: Request>(
+        &mut self,
+        responder: &Arc<Mutex<Box<dyn Responder<R>>>>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions().respond_to(responder)
     }
 }
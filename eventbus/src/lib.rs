@@ -1 +1,5 @@
+pub mod bridge;
 pub mod event;
+pub mod queued;
+pub mod request;
+pub mod service;
@@ -0,0 +1,378 @@
+use error::Error;
+use log::error;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle, ThreadId};
+
+use crate::event::{
+    lock_subscribers, notify_subscribers, Dispatcher, ErrorCode, Event, EventBus,
+    EventBusSubscriptions, Origin, Replay, Subscriber, Subscribers, Subscription,
+    SubscriptionOptions, SubscriptionRegistry,
+};
+use crate::request::{self, PendingResponse, Request, RequestBus, Respond, Responder};
+
+///////////////////////////////////////////////////////////////////////////////
+/// Defines what trigger_event does, when the queue of the bus is full
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum QueuePolicy {
+    /// Wait until the dispatcher thread made room for the event
+    Block,
+    /// Drop the oldest queued event in favour of the new one
+    DropOldest,
+    /// Reject the new event with ErrorCode::QueueFull
+    Error,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// State shared between the bus and its dispatcher thread
+struct Queued {
+    event: Box<dyn Event>,
+    sticky: bool,
+    respond: Option<Respond>,
+    origin: Option<Origin>,
+}
+struct Queue {
+    events: VecDeque<Queued>,
+    /// replays of new subscriptions, delivered before the next event
+    replays: VecDeque<Replay>,
+    capacity: usize,
+    dispatching: bool,
+    running: bool,
+    dispatcher: Option<ThreadId>,
+}
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+impl Dispatcher for Shared {
+    fn replay(&self, _subscribers: &Arc<Mutex<Subscribers>>, replay: Replay) {
+        match self.queue.lock() {
+            Ok(mut queue) if queue.running => {
+                queue.replays.push_back(replay);
+                self.changed.notify_all();
+            }
+            Ok(_) => (),
+            Err(err) => error!(target: "eventbus", "Could not queue replay ({err})"),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Asynchronous event bus: trigger_event only enqueues the event and returns.
+/// A dedicated dispatcher thread delivers the events to the subscribers in the
+/// order they were triggered.
+///
+/// Subscribers and responders are called on the dispatcher thread, so they
+/// must not call flush or shutdown of the bus that notifies them, nor wait for
+/// the response of a request sent on it.
+pub struct EventBusQueued {
+    shared: Arc<Shared>,
+    policy: QueuePolicy,
+    thread: Option<JoinHandle<()>>,
+}
+impl EventBusQueued {
+    pub fn new(capacity: usize, policy: QueuePolicy) -> EventBusQueued {
+        EventBusQueued::with_history(capacity, policy, 0)
+    }
+
+    /// Creates a bus keeping the last history_capacity events for replays
+    pub fn with_history(
+        capacity: usize,
+        policy: QueuePolicy,
+        history_capacity: usize,
+    ) -> EventBusQueued {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                events: VecDeque::with_capacity(capacity),
+                replays: VecDeque::new(),
+                capacity: capacity.max(1),
+                dispatching: false,
+                running: true,
+                dispatcher: None,
+            }),
+            changed: Condvar::new(),
+            subscribers: Subscribers::new(history_capacity),
+        });
+
+        let clone = shared.clone();
+        let thread = thread::spawn(move || EventBusQueued::dispatch(clone));
+        if let Ok(mut queue) = shared.queue.lock() {
+            queue.dispatcher = Some(thread.thread().id());
+        }
+
+        EventBusQueued {
+            shared,
+            policy,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn subscriptions(&self) -> EventBusSubscriptions {
+        EventBusSubscriptions::new(&self.shared.subscribers, self.shared.clone())
+    }
+
+    /// Blocks until every event that was queued before is delivered. Fails
+    /// with ErrorCode::ShutDown once the bus is shut down.
+    pub fn flush(&self) -> Result<(), Error<ErrorCode>> {
+        let mut queue = self.lock_queue()?;
+        if queue.dispatcher == Some(thread::current().id()) {
+            return Err(Error::new(
+                ErrorCode::CouldNotQueueEvent,
+                "Could not flush the event bus from its dispatcher thread",
+            ));
+        }
+        loop {
+            if !queue.running {
+                return Err(Error::new(ErrorCode::ShutDown, "Event bus is shut down"));
+            }
+            if queue.events.is_empty() && queue.replays.is_empty() && !queue.dispatching {
+                return Ok(());
+            }
+            queue = self.wait(queue)?;
+        }
+    }
+
+    /// Stops accepting new events, delivers the queued ones and stops the
+    /// dispatcher thread
+    pub fn shutdown(&mut self) -> Result<(), Error<ErrorCode>> {
+        {
+            let mut queue = self.lock_queue()?;
+            if queue.dispatcher == Some(thread::current().id()) {
+                return Err(Error::new(
+                    ErrorCode::ShutDown,
+                    "Could not shut down the event bus from its dispatcher thread",
+                ));
+            }
+            queue.running = false;
+            self.shared.changed.notify_all();
+        }
+
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| Error::new(ErrorCode::ShutDown, "Dispatcher thread panicked")),
+            None => Ok(()),
+        }
+    }
+
+    fn enqueue(
+        &mut self,
+        event: Box<dyn Event>,
+        sticky: bool,
+        respond: Option<Respond>,
+        origin: Option<Origin>,
+    ) -> Result<(), Error<ErrorCode>> {
+        let mut queue = self.lock_queue()?;
+        loop {
+            if !queue.running {
+                return Err(Error::new(ErrorCode::ShutDown, "Event bus is shut down"));
+            }
+            if queue.events.len() < queue.capacity {
+                break;
+            }
+            match self.policy {
+                // blocking the dispatcher thread on its own queue would never return
+                QueuePolicy::Block if queue.dispatcher != Some(thread::current().id()) => {
+                    queue = self.wait(queue)?;
+                }
+                QueuePolicy::DropOldest => {
+                    let dropped = queue.events.pop_front().and_then(|queued| queued.respond);
+                    if let Some(respond) = dropped {
+                        respond.fail(Error::new(
+                            ErrorCode::QueueFull,
+                            "Request was dropped from the full event queue",
+                        ));
+                    }
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorCode::QueueFull,
+                        format!("Event queue is full ({} events)", queue.capacity).as_str(),
+                    ))
+                }
+            }
+        }
+
+        queue.events.push_back(Queued {
+            event,
+            sticky,
+            respond,
+            origin,
+        });
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    fn dispatch(shared: Arc<Shared>) {
+        let _stop = StopOnPanic(&shared);
+        loop {
+            let queued = {
+                let mut queue = match shared.queue.lock() {
+                    Ok(guard) => guard,
+                    Err(err) => {
+                        error!(target: "eventbus", "Dispatcher stopped ({err})");
+                        return;
+                    }
+                };
+                queue.dispatching = false;
+                shared.changed.notify_all();
+
+                while queue.events.is_empty() && queue.replays.is_empty() && queue.running {
+                    queue = match shared.changed.wait(queue) {
+                        Ok(guard) => guard,
+                        Err(err) => {
+                            error!(target: "eventbus", "Dispatcher stopped ({err})");
+                            return;
+                        }
+                    };
+                }
+                if let Some(replay) = queue.replays.pop_front() {
+                    queue.dispatching = true;
+                    drop(queue);
+                    replay.deliver(&shared.subscribers);
+                    continue;
+                }
+                match queue.events.pop_front() {
+                    Some(queued) => {
+                        queue.dispatching = true;
+                        shared.changed.notify_all();
+                        queued
+                    }
+                    None => return,
+                }
+            };
+
+            let event: Arc<dyn Event> = Arc::from(queued.event);
+            if let Some(respond) = queued.respond {
+                respond.respond(event.as_ref());
+            }
+            let subscribers = match lock_subscribers(&shared.subscribers) {
+                Ok(mut subscribers) => {
+                    subscribers.record(&event, queued.sticky, queued.origin);
+                    subscribers.subscribers_of(event.as_ref())
+                }
+                Err(err) => {
+                    error!(target: "eventbus", "{}", err.message);
+                    continue;
+                }
+            };
+            let notified = Origin::delivering(queued.origin, || {
+                notify_subscribers(&subscribers, event.as_ref())
+            });
+            if let Err(err) = notified {
+                error!(target: "eventbus", "{}", err.message);
+            }
+        }
+    }
+
+    fn lock_queue(&self) -> Result<MutexGuard<'_, Queue>, Error<ErrorCode>> {
+        self.shared.queue.lock().map_err(|err| {
+            Error::new(
+                ErrorCode::CouldNotQueueEvent,
+                format!("Could not lock event queue ({err})").as_str(),
+            )
+        })
+    }
+
+    fn wait<'a>(
+        &self,
+        queue: MutexGuard<'a, Queue>,
+    ) -> Result<MutexGuard<'a, Queue>, Error<ErrorCode>> {
+        self.shared.changed.wait(queue).map_err(|err| {
+            Error::new(
+                ErrorCode::CouldNotQueueEvent,
+                format!("Could not wait for event queue ({err})").as_str(),
+            )
+        })
+    }
+}
+/// Stops the bus if a subscriber or responder panics on the dispatcher
+/// thread, so flush and trigger_event fail instead of waiting for it forever.
+/// The queued events are dropped, their requests fail with ErrorCode::ShutDown.
+struct StopOnPanic<'a>(&'a Shared);
+impl Drop for StopOnPanic<'_> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        let mut queue = match self.0.queue.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        error!(target: "eventbus", "Dispatcher thread panicked, the event bus is shut down");
+        queue.running = false;
+        queue.dispatching = false;
+        queue.events.clear();
+        queue.replays.clear();
+        self.0.changed.notify_all();
+    }
+}
+impl Drop for EventBusQueued {
+    fn drop(&mut self) {
+        if let Err(err) = self.shutdown() {
+            error!(target: "eventbus", "{}", err.message);
+        }
+    }
+}
+impl EventBus for EventBusQueued {
+    fn trigger_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>> {
+        self.enqueue(event, false, None, None)
+    }
+
+    fn trigger_sticky_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>> {
+        self.enqueue(event, true, None, None)
+    }
+
+    fn trigger_event_from(
+        &mut self,
+        event: Box<dyn Event>,
+        origin: Origin,
+    ) -> Result<(), Error<ErrorCode>> {
+        self.enqueue(event, false, None, Some(origin))
+    }
+}
+impl RequestBus for EventBusQueued {
+    fn send_request<R: Request>(
+        &mut self,
+        request: R,
+    ) -> Result<PendingResponse<R::Response>, Error<ErrorCode>> {
+        let (response, respond) = request::prepare::<R>(&self.shared.subscribers)?;
+        self.enqueue(Box::new(request), false, Some(respond), None)?;
+        Ok(response)
+    }
+}
+impl SubscriptionRegistry for EventBusQueued {
+    fn subscribe_event_with<T: Event>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions()
+            .subscribe_event_with(subscriber, options)
+    }
+
+    fn subscribe_trait_with<T: ?Sized + 'static>(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<T>>>>,
+        options: SubscriptionOptions<T>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions()
+            .subscribe_trait_with(subscriber, options)
+    }
+
+    fn subscribe_all_with(
+        &mut self,
+        subscriber: &Arc<Mutex<Box<dyn Subscriber<dyn Event>>>>,
+        options: SubscriptionOptions<dyn Event>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions().subscribe_all_with(subscriber, options)
+    }
+
+    fn respond_to<R: Request>(
+        &mut self,
+        responder: &Arc<Mutex<Box<dyn Responder<R>>>>,
+    ) -> Result<Subscription, Error<ErrorCode>> {
+        self.subscriptions().respond_to(responder)
+    }
+}
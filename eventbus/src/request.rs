@@ -0,0 +1,190 @@
+use condvar::OptionalConditionalVariable;
+use error::Error;
+use std::any::{Any, TypeId};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use crate::event::{lock_subscribers, ErrorCode, Event, Subscribers, Subscription};
+
+///////////////////////////////////////////////////////////////////////////////
+/// A request is an event a single responder answers with a typed response.
+/// Subscribers of the request type get it like any other event after the
+/// responder answered.
+pub trait Request: Event {
+    type Response: Send + 'static;
+}
+/// Answers the requests of type R. There is at most one responder per request
+/// type on a bus.
+pub trait Responder<R: Request>: Send {
+    fn respond(&mut self, request: &R) -> R::Response;
+}
+
+/// Sending of requests
+pub trait RequestBus {
+    /// Sends the request and returns without waiting for the response.
+    /// Fails with ErrorCode::NoResponder if no responder is registered.
+    fn send_request<R: Request>(
+        &mut self,
+        request: R,
+    ) -> Result<PendingResponse<R::Response>, Error<ErrorCode>>;
+
+    /// Sends the request and blocks until the response arrives
+    fn request<R: Request>(
+        &mut self,
+        request: R,
+        timeout: Duration,
+    ) -> Result<R::Response, Error<ErrorCode>> {
+        self.send_request(request)?.wait(timeout)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Response of a sent request that might not have arrived yet
+pub struct PendingResponse<T> {
+    response: OptionalConditionalVariable<Result<T, Error<ErrorCode>>>,
+}
+impl<T> PendingResponse<T> {
+    /// Blocks until the response arrives, at most for the given timeout
+    pub fn wait(self, timeout: Duration) -> Result<T, Error<ErrorCode>> {
+        match self.response.wait_timeout(timeout) {
+            Some(response) => *response,
+            None => Err(Error::new(
+                ErrorCode::Timeout,
+                format!("No response within {} ms", timeout.as_millis()).as_str(),
+            )),
+        }
+    }
+}
+
+/// Calls the responder of a request and hands its response to the requester.
+/// It is called with the request right before the subscribers are notified.
+/// A request dropped before that, e.g. by QueuePolicy::DropOldest or a
+/// dispatcher that stopped, hands an error to the requester instead.
+pub(crate) struct Respond {
+    deliver: Option<Deliver>,
+}
+/// Hands the response to the request, or the error, to the requester
+type Deliver = Box<dyn FnOnce(Result<&dyn Event, Error<ErrorCode>>) + Send>;
+impl Respond {
+    pub(crate) fn respond(mut self, request: &dyn Event) {
+        if let Some(deliver) = self.deliver.take() {
+            deliver(Ok(request));
+        }
+    }
+
+    pub(crate) fn fail(mut self, err: Error<ErrorCode>) {
+        if let Some(deliver) = self.deliver.take() {
+            deliver(Err(err));
+        }
+    }
+}
+impl Drop for Respond {
+    fn drop(&mut self) {
+        if let Some(deliver) = self.deliver.take() {
+            deliver(Err(Error::new(
+                ErrorCode::ShutDown,
+                "Request was dropped before it was answered",
+            )));
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Type erased responder, so responders of different request types can be
+/// stored in the same map
+pub(crate) trait ResponderEntry: Send + Sync {
+    fn id(&self) -> u64;
+    fn is_alive(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+}
+struct Entry<R: Request> {
+    id: u64,
+    responder: Weak<Mutex<Box<dyn Responder<R>>>>,
+}
+impl<R: Request> ResponderEntry for Entry<R> {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn is_alive(&self) -> bool {
+        self.responder.strong_count() > 0
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub(crate) fn respond_to<R: Request>(
+    subscribers: &Arc<Mutex<Subscribers>>,
+    responder: &Arc<Mutex<Box<dyn Responder<R>>>>,
+) -> Result<Subscription, Error<ErrorCode>> {
+    let mut guard = lock_subscribers(subscribers)?;
+    let alive = guard
+        .responders
+        .get(&TypeId::of::<R>())
+        .is_some_and(|responder| responder.is_alive());
+    if alive {
+        return Err(Error::new(
+            ErrorCode::MultipleResponders,
+            format!(
+                "Request {} already has a responder",
+                std::any::type_name::<R>()
+            )
+            .as_str(),
+        ));
+    }
+
+    let id = guard.next_id();
+    guard.responders.insert(
+        TypeId::of::<R>(),
+        Arc::new(Entry {
+            id,
+            responder: Arc::downgrade(responder),
+        }),
+    );
+    Ok(Subscription::new(subscribers, id))
+}
+
+/// Looks up the responder of R and prepares the delivery of its response
+pub(crate) fn prepare<R: Request>(
+    subscribers: &Mutex<Subscribers>,
+) -> Result<(PendingResponse<R::Response>, Respond), Error<ErrorCode>> {
+    let responder = lock_subscribers(subscribers)?
+        .responders
+        .get(&TypeId::of::<R>())
+        .and_then(|entry| entry.as_any().downcast_ref::<Entry<R>>())
+        .filter(|entry| entry.is_alive())
+        .map(|entry| entry.responder.clone())
+        .ok_or_else(|| no_responder::<R>())?;
+
+    let response = OptionalConditionalVariable::new();
+    let mut notify = response.clone();
+    let respond = Respond {
+        deliver: Some(Box::new(
+            move |request: Result<&dyn Event, Error<ErrorCode>>| {
+                let result = request.and_then(|event| {
+                    match (responder.upgrade(), event.downcast_ref::<R>()) {
+                        (Some(responder), Some(request)) => match responder.lock() {
+                            Ok(mut responder) => Ok(responder.respond(request)),
+                            Err(err) => Err(Error::new(
+                                ErrorCode::CouldNotNotifySubscriber,
+                                format!("Could not call responder ({err})").as_str(),
+                            )),
+                        },
+                        _ => Err(no_responder::<R>()),
+                    }
+                });
+                notify.notify(result);
+            },
+        )),
+    };
+    Ok((PendingResponse { response }, respond))
+}
+
+fn no_responder<R: Request>() -> Error<ErrorCode> {
+    Error::new(
+        ErrorCode::NoResponder,
+        format!("No responder for request {}", std::any::type_name::<R>()).as_str(),
+    )
+}
@@ -0,0 +1,110 @@
+use di::registry::{Registry, Resolver};
+use di::service::{Lifetime, Service};
+use error::Error;
+use std::ops::{Deref, DerefMut};
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+use crate::event::{ErrorCode, Event, EventBus, EventBusDefault, EventBusSubscriptions, Origin};
+
+///////////////////////////////////////////////////////////////////////////////
+/// Event bus as a service of the DI registry. The registry creates one bus per
+/// di::registry::Session, so events never leave the session they are triggered
+/// in. Clearing the session ends all subscriptions of its bus.
+pub trait EventBusService: EventBus + Service {
+    /// Subscribers of the bus of this session
+    fn subscriptions(&self) -> EventBusSubscriptions;
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// EventBusService delivering events synchronously with EventBusDefault
+#[derive(Castable)]
+#[Traits(EventBusService)]
+pub struct EventBusServiceDefault(EventBusDefault);
+impl EventBusServiceDefault {
+    pub fn new() -> EventBusServiceDefault {
+        EventBusServiceDefault(EventBusDefault::new())
+    }
+
+    pub fn factory(
+        _: &Resolver,
+    ) -> Result<Box<dyn Castable + Sync>, Error<di::registry::ErrorCode>> {
+        Ok(Box::new(EventBusServiceDefault::new()))
+    }
+
+    /// Registers EventBusServiceDefault as dyn EventBusService in the global
+    /// container
+    pub fn register() -> Result<(), Error<di::registry::ErrorCode>> {
+        EventBusServiceDefault::register_in(Registry::global())
+    }
+
+    /// Registers EventBusServiceDefault as dyn EventBusService in the given
+    /// container
+    pub fn register_in(registry: &Registry) -> Result<(), Error<di::registry::ErrorCode>> {
+        registry.register_service::<dyn EventBusService>(
+            EventBusServiceDefault::factory,
+            Lifetime::Scoped,
+        )
+    }
+
+    /// Unregisters the EventBusService of the global container and drops the
+    /// buses of all sessions
+    pub fn unregister() -> Result<(), Error<di::registry::ErrorCode>> {
+        EventBusServiceDefault::unregister_from(Registry::global())
+    }
+
+    /// Unregisters the EventBusService of the given container and drops the
+    /// buses of all its sessions
+    pub fn unregister_from(registry: &Registry) -> Result<(), Error<di::registry::ErrorCode>> {
+        registry.unregister_service::<dyn EventBusService>()
+    }
+}
+impl Default for EventBusServiceDefault {
+    fn default() -> Self {
+        EventBusServiceDefault::new()
+    }
+}
+impl EventBus for EventBusServiceDefault {
+    fn trigger_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>> {
+        self.0.trigger_event(event)
+    }
+
+    fn trigger_sticky_event(&mut self, event: Box<dyn Event>) -> Result<(), Error<ErrorCode>> {
+        self.0.trigger_sticky_event(event)
+    }
+
+    fn trigger_event_from(
+        &mut self,
+        event: Box<dyn Event>,
+        origin: Origin,
+    ) -> Result<(), Error<ErrorCode>> {
+        self.0.trigger_event_from(event, origin)
+    }
+}
+impl EventBusService for EventBusServiceDefault {
+    fn subscriptions(&self) -> EventBusSubscriptions {
+        self.0.subscriptions()
+    }
+}
+impl Service for EventBusServiceDefault {
+    fn uninitialize(&mut self) -> Result<(), Error<di::registry::ErrorCode>> {
+        self.0.clear().map_err(|err| {
+            Error::new(
+                di::registry::ErrorCode::ServiceError,
+                format!("Could not clear the event bus ({})", err.message).as_str(),
+            )
+        })
+    }
+}
+impl Deref for EventBusServiceDefault {
+    type Target = EventBusDefault;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl DerefMut for EventBusServiceDefault {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,222 @@
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use error::Error;
+use eventbus::bridge::{EventBridge, LineSerializer};
+use eventbus::event::{EventBus, EventBusDefault, Propagation, Subscriber, SubscriptionRegistry};
+use eventbus::queued::{EventBusQueued, QueuePolicy};
+use eventbus_derive::Event;
+use traitcast::Castable;
+use traitcast_derive::Castable;
+use tungstenite::Message;
+use websocket::{OnTextMessageSubscription, Openable, Subscriptions, TextSender};
+use websocket_lite_impl::WebSocketLite;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Castable, Event)]
+struct SimpleEvent {
+    value: u32,
+}
+#[derive(Castable, Event)]
+struct RemoteEvent {
+    value: u32,
+}
+
+struct Recorder {
+    values: Sender<u32>,
+}
+impl Subscriber<SimpleEvent> for Recorder {
+    fn notify(&mut self, event: &SimpleEvent) -> Propagation {
+        self.values.send(event.value).ok();
+        Propagation::Continue
+    }
+}
+impl Subscriber<RemoteEvent> for Recorder {
+    fn notify(&mut self, event: &RemoteEvent) -> Propagation {
+        self.values.send(event.value).ok();
+        Propagation::Continue
+    }
+}
+
+#[derive(Castable, Event)]
+struct GateEvent {}
+
+/// Holds the dispatcher thread of a queued bus until it is released
+struct Gate {
+    release: Mutex<Receiver<()>>,
+}
+impl Subscriber<GateEvent> for Gate {
+    fn notify(&mut self, _: &GateEvent) -> Propagation {
+        self.release.lock().unwrap().recv().ok();
+        Propagation::Continue
+    }
+}
+
+/// Keeps the messages sent to the peer
+struct SentMessages {
+    messages: Sender<String>,
+}
+impl TextSender for SentMessages {
+    fn send(&mut self, message: &str) -> Result<(), Error<websocket::ErrorCode>> {
+        self.messages.send(message.to_string()).ok();
+        Ok(())
+    }
+}
+
+/// Remote peer sending the given messages as soon as the bridge connects and
+/// reporting every message it receives
+fn start_peer(messages: Vec<&'static str>) -> (u16, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (received, receiver) = channel();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut socket = tungstenite::accept(stream).unwrap();
+        for message in messages {
+            socket
+                .write_message(Message::Text(message.to_string()))
+                .unwrap();
+        }
+        loop {
+            match socket.read_message() {
+                Ok(Message::Text(text)) => {
+                    received.send(text).ok();
+                }
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => (),
+            }
+        }
+    });
+    (port, receiver)
+}
+
+#[test]
+fn bridge_events() {
+    let (port, peer) = start_peer(vec![
+        "Simple\n9",
+        "Remote\n5",
+        "Unknown\n1",
+        "Remote\nmalformed",
+        "Remote\n6",
+    ]);
+
+    let event_bus = EventBusDefault::new();
+    let mut subscriptions = event_bus.subscriptions();
+    let event_bus: Arc<Mutex<dyn EventBus + Send>> = Arc::new(Mutex::new(event_bus));
+    let web_socket = Arc::new(Mutex::new(WebSocketLite::default()));
+
+    let mut bridge = EventBridge::new(
+        event_bus.clone(),
+        subscriptions.clone(),
+        web_socket.clone(),
+        Box::new(LineSerializer),
+    );
+    assert!(bridge
+        .forward::<SimpleEvent>("Simple", |event| event.value.to_string())
+        .is_ok());
+    assert!(bridge
+        .receive::<SimpleEvent>("Simple", |payload| payload
+            .parse()
+            .ok()
+            .map(|value| SimpleEvent { value }))
+        .is_ok());
+    assert!(bridge
+        .receive::<RemoteEvent>("Remote", |payload| payload
+            .parse()
+            .ok()
+            .map(|value| RemoteEvent { value }))
+        .is_ok());
+    let bridge: Arc<Mutex<Box<dyn Castable>>> = Arc::new(Mutex::new(Box::new(bridge)));
+
+    let (simple_values, simple_receiver) = channel();
+    let (remote_values, remote_receiver) = channel();
+    let simple_recorder: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(Recorder {
+            values: simple_values,
+        })));
+    let remote_recorder: Arc<Mutex<Box<dyn Subscriber<RemoteEvent>>>> =
+        Arc::new(Mutex::new(Box::new(Recorder {
+            values: remote_values,
+        })));
+    let _simple_subscription = subscriptions.subscribe_event(&simple_recorder).unwrap();
+    let _remote_subscription = subscriptions.subscribe_event(&remote_recorder).unwrap();
+
+    {
+        let mut web_socket = web_socket.lock().unwrap();
+        assert!(web_socket.subscribe(bridge.clone()).is_ok());
+        assert!(web_socket
+            .open(format!("ws://127.0.0.1:{port}").as_str(), None)
+            .is_ok());
+    }
+
+    // events of the peer reach the local subscribers, invalid messages are
+    // skipped
+    assert_eq!(simple_receiver.recv_timeout(TIMEOUT), Ok(9));
+    assert_eq!(remote_receiver.recv_timeout(TIMEOUT), Ok(5));
+    assert_eq!(remote_receiver.recv_timeout(TIMEOUT), Ok(6));
+
+    // local events reach the peer, the received one is not sent back
+    assert!(event_bus
+        .lock()
+        .unwrap()
+        .trigger_event(Box::new(SimpleEvent { value: 7 }))
+        .is_ok());
+    assert_eq!(peer.recv_timeout(TIMEOUT), Ok("Simple\n7".to_string()));
+    assert_eq!(simple_receiver.recv_timeout(TIMEOUT), Ok(7));
+    assert!(event_bus
+        .lock()
+        .unwrap()
+        .trigger_event(Box::new(RemoteEvent { value: 8 }))
+        .is_ok());
+    assert!(peer.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn forward_a_local_event_queued_before_a_received_one() {
+    let event_bus = Arc::new(Mutex::new(EventBusQueued::new(8, QueuePolicy::Block)));
+    let mut subscriptions = event_bus.lock().unwrap().subscriptions();
+    let (messages, peer) = channel();
+    let mut bridge = EventBridge::new(
+        event_bus.clone(),
+        subscriptions.clone(),
+        Arc::new(Mutex::new(SentMessages { messages })),
+        Box::new(LineSerializer),
+    );
+    bridge
+        .forward::<SimpleEvent>("Simple", |event| event.value.to_string())
+        .unwrap();
+    bridge
+        .receive::<SimpleEvent>("Simple", |payload| {
+            payload.parse().ok().map(|value| SimpleEvent { value })
+        })
+        .unwrap();
+
+    let (release, released) = channel();
+    let gate: Arc<Mutex<Box<dyn Subscriber<GateEvent>>>> = Arc::new(Mutex::new(Box::new(Gate {
+        release: Mutex::new(released),
+    })));
+    let _gate_subscription = subscriptions.subscribe_event(&gate).unwrap();
+    let (values, local) = channel();
+    let recorder: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(Recorder { values })));
+    let _subscription = subscriptions.subscribe_event(&recorder).unwrap();
+
+    // the local event is still queued when the one of the peer arrives
+    {
+        let mut event_bus = event_bus.lock().unwrap();
+        event_bus.trigger_event(Box::new(GateEvent {})).unwrap();
+        event_bus
+            .trigger_event(Box::new(SimpleEvent { value: 7 }))
+            .unwrap();
+    }
+    bridge.on_message("Simple\n9");
+    release.send(()).unwrap();
+    event_bus.lock().unwrap().flush().unwrap();
+
+    assert_eq!(local.try_iter().collect::<Vec<u32>>(), vec![7, 9]);
+    assert_eq!(peer.try_iter().collect::<Vec<String>>(), vec!["Simple\n7"]);
+}
@@ -1,8 +1,16 @@
-use di_derive::inject;
-use eventbus::event::{Event, EventBus};
+use std::sync::{Arc, Mutex};
 
-mod common;
+use di_derive::inject;
+use error::Error;
+use eventbus::event::{
+    ErrorCode, Event, EventBus, EventBusDefault, Origin, Propagation, Subscriber,
+    SubscriptionOptions, SubscriptionRegistry,
+};
+use eventbus::service::{EventBusService, EventBusServiceDefault};
+use eventbus_derive::Event;
+use traitcast_derive::Castable;
 
+#[derive(Castable, Event)]
 struct SimpleEvent {
     value: u32,
 }
@@ -11,10 +19,113 @@ impl SimpleEvent {
         SimpleEvent { value }
     }
 }
-impl Event for SimpleEvent {}
+#[derive(Castable, Event)]
+struct OtherEvent {}
+
+trait ConnectionEvent {
+    fn connected(&self) -> bool;
+}
+#[derive(Castable, Event)]
+#[Traits(ConnectionEvent)]
+struct Connected {}
+impl ConnectionEvent for Connected {
+    fn connected(&self) -> bool {
+        true
+    }
+}
+#[derive(Castable, Event)]
+#[Traits(ConnectionEvent)]
+struct Disconnected {}
+impl ConnectionEvent for Disconnected {
+    fn connected(&self) -> bool {
+        false
+    }
+}
+
+struct ConnectionSubscriber {
+    states: Arc<Mutex<Vec<bool>>>,
+}
+impl ConnectionSubscriber {
+    pub fn shared(
+        states: Arc<Mutex<Vec<bool>>>,
+    ) -> Arc<Mutex<Box<dyn Subscriber<dyn ConnectionEvent>>>> {
+        Arc::new(Mutex::new(Box::new(ConnectionSubscriber { states })))
+    }
+}
+impl Subscriber<dyn ConnectionEvent> for ConnectionSubscriber {
+    fn notify(&mut self, event: &dyn ConnectionEvent) -> Propagation {
+        self.states.lock().unwrap().push(event.connected());
+        Propagation::Continue
+    }
+}
+
+/// Records the origin of every event
+struct OriginSubscriber {
+    origins: Arc<Mutex<Vec<Option<Origin>>>>,
+}
+impl Subscriber<SimpleEvent> for OriginSubscriber {
+    fn notify(&mut self, _: &SimpleEvent) -> Propagation {
+        self.origins.lock().unwrap().push(Origin::current());
+        Propagation::Continue
+    }
+}
+
+/// Records its name and consumes events with the given value
+struct OrderedSubscriber {
+    name: &'static str,
+    order: Arc<Mutex<Vec<&'static str>>>,
+    consume: Option<u32>,
+}
+impl OrderedSubscriber {
+    pub fn shared(
+        name: &'static str,
+        order: Arc<Mutex<Vec<&'static str>>>,
+        consume: Option<u32>,
+    ) -> Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> {
+        Arc::new(Mutex::new(Box::new(OrderedSubscriber {
+            name,
+            order,
+            consume,
+        })))
+    }
+}
+impl Subscriber<SimpleEvent> for OrderedSubscriber {
+    fn notify(&mut self, event: &SimpleEvent) -> Propagation {
+        self.order.lock().unwrap().push(self.name);
+        match self.consume {
+            Some(value) if value == event.value => Propagation::Consumed,
+            _ => Propagation::Continue,
+        }
+    }
+}
+
+struct AuditSubscriber {
+    names: Arc<Mutex<Vec<&'static str>>>,
+}
+impl Subscriber<dyn Event> for AuditSubscriber {
+    fn notify(&mut self, event: &dyn Event) -> Propagation {
+        self.names.lock().unwrap().push(event.type_name());
+        Propagation::Continue
+    }
+}
+
+struct SimpleSubscriber {
+    values: Arc<Mutex<Vec<u32>>>,
+}
+impl SimpleSubscriber {
+    pub fn shared(values: Arc<Mutex<Vec<u32>>>) -> Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> {
+        Arc::new(Mutex::new(Box::new(SimpleSubscriber { values })))
+    }
+}
+impl Subscriber<SimpleEvent> for SimpleSubscriber {
+    fn notify(&mut self, event: &SimpleEvent) -> Propagation {
+        self.values.lock().unwrap().push(event.value);
+        Propagation::Continue
+    }
+}
 
 #[inject(event_bus)]
-fn func_trigger_event(event_bus: &mut dyn common::EventBusService) {
+fn func_trigger_event(event_bus: &mut dyn EventBusService) {
     let event = SimpleEvent::new(1);
     let res = event_bus.trigger_event(Box::new(event));
     assert!(res.is_ok());
@@ -22,15 +133,352 @@ fn func_trigger_event(event_bus: &mut dyn common::EventBusService) {
 
 #[test]
 fn trigger_event() {
-    common::setup();
+    EventBusServiceDefault::register().unwrap();
     func_trigger_event().unwrap();
-    common::teardown();
+    EventBusServiceDefault::unregister().unwrap();
+}
+#[test]
+fn subscribe_event() {
+    let mut event_bus = EventBusDefault::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+    let other_subscriber = SimpleSubscriber::shared(values.clone());
+
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    let _other_subscription = event_bus.subscribe_event(&other_subscriber).unwrap();
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    assert!(event_bus.trigger_event(Box::new(OtherEvent {})).is_ok());
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(2)))
+        .is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![1, 1, 2, 2]);
+}
+#[test]
+fn already_subscribed() {
+    let mut event_bus = EventBusDefault::new();
+    let subscriber = SimpleSubscriber::shared(Arc::new(Mutex::new(Vec::new())));
+
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    assert_eq!(
+        event_bus.subscribe_event(&subscriber).err(),
+        Some(Error::new(ErrorCode::AlreadySubscribed, ""))
+    );
+}
+#[test]
+fn unsubscribe_event() {
+    let mut event_bus = EventBusDefault::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+
+    let subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    assert!(subscription.unsubscribe().is_ok());
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(2)))
+        .is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![1]);
 }
 #[test]
-fn subscribe_event() {}
+fn unsubscribe_on_drop() {
+    let mut event_bus = EventBusDefault::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+
+    {
+        let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+        assert!(event_bus
+            .trigger_event(Box::new(SimpleEvent::new(1)))
+            .is_ok());
+    }
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(2)))
+        .is_ok());
+    // the subscriber can subscribe again after its subscription ended
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(3)))
+        .is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![1, 3]);
+}
 #[test]
-fn already_subscribed() {}
+fn dropped_subscriber() {
+    let mut event_bus = EventBusDefault::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+
+    let subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    drop(subscriber);
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(2)))
+        .is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![1]);
+    assert_eq!(
+        subscription.unsubscribe(),
+        Err(Error::new(ErrorCode::NotSubscribed, ""))
+    );
+}
 #[test]
-fn unsubscribe_event() {}
+fn not_subscribed_on_unsubscribe() {
+    let mut event_bus = EventBusDefault::new();
+    let subscriber = SimpleSubscriber::shared(Arc::new(Mutex::new(Vec::new())));
+
+    let subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    drop(event_bus);
+    assert_eq!(
+        subscription.unsubscribe(),
+        Err(Error::new(ErrorCode::NotSubscribed, ""))
+    );
+}
 #[test]
-fn not_subscribed_on_unsubscribe() {}
+fn subscribe_trait_event() {
+    let mut event_bus = EventBusDefault::new();
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = ConnectionSubscriber::shared(states.clone());
+
+    let subscription = event_bus.subscribe_trait(&subscriber).unwrap();
+    assert_eq!(
+        event_bus.subscribe_trait(&subscriber).err(),
+        Some(Error::new(ErrorCode::AlreadySubscribed, ""))
+    );
+    assert!(event_bus.trigger_event(Box::new(Connected {})).is_ok());
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    assert!(event_bus.trigger_event(Box::new(Disconnected {})).is_ok());
+    assert_eq!(*states.lock().unwrap(), vec![true, false]);
+
+    assert!(subscription.unsubscribe().is_ok());
+    assert!(event_bus.trigger_event(Box::new(Connected {})).is_ok());
+    assert_eq!(*states.lock().unwrap(), vec![true, false]);
+}
+#[test]
+fn subscribe_all_events() {
+    let mut event_bus = EventBusDefault::new();
+    let names = Arc::new(Mutex::new(Vec::new()));
+    let subscriber: Arc<Mutex<Box<dyn Subscriber<dyn Event>>>> =
+        Arc::new(Mutex::new(Box::new(AuditSubscriber {
+            names: names.clone(),
+        })));
+
+    let subscription = event_bus.subscribe_all(&subscriber).unwrap();
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    assert!(event_bus.trigger_event(Box::new(Connected {})).is_ok());
+    assert!(subscription.unsubscribe().is_ok());
+    assert!(event_bus.trigger_event(Box::new(OtherEvent {})).is_ok());
+
+    let names = names.lock().unwrap();
+    assert_eq!(names.len(), 2);
+    assert!(names[0].ends_with("SimpleEvent"));
+    assert!(names[1].ends_with("Connected"));
+}
+#[test]
+fn notify_by_priority() {
+    let mut event_bus = EventBusDefault::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    let low = OrderedSubscriber::shared("low", order.clone(), None);
+    let first = OrderedSubscriber::shared("first", order.clone(), None);
+    let second = OrderedSubscriber::shared("second", order.clone(), None);
+    let high = OrderedSubscriber::shared("high", order.clone(), None);
+    let _subscriptions = [
+        event_bus
+            .subscribe_event_with(&low, SubscriptionOptions::default().with_priority(-1))
+            .unwrap(),
+        event_bus.subscribe_event(&first).unwrap(),
+        event_bus.subscribe_event(&second).unwrap(),
+        event_bus
+            .subscribe_event_with(&high, SubscriptionOptions::default().with_priority(10))
+            .unwrap(),
+    ];
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    assert_eq!(
+        *order.lock().unwrap(),
+        vec!["high", "first", "second", "low"]
+    );
+}
+#[test]
+fn filter_events() {
+    let mut event_bus = EventBusDefault::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let connection_subscriber = ConnectionSubscriber::shared(states.clone());
+
+    let _subscription = event_bus
+        .subscribe_event_with(
+            &subscriber,
+            SubscriptionOptions::<SimpleEvent>::default().with_filter(|event| event.value % 2 == 0),
+        )
+        .unwrap();
+    let _connection_subscription = event_bus
+        .subscribe_trait_with(
+            &connection_subscriber,
+            SubscriptionOptions::<dyn ConnectionEvent>::default()
+                .with_filter(|event| !event.connected()),
+        )
+        .unwrap();
+    for value in 1..=4 {
+        assert!(event_bus
+            .trigger_event(Box::new(SimpleEvent::new(value)))
+            .is_ok());
+    }
+    assert!(event_bus.trigger_event(Box::new(Connected {})).is_ok());
+    assert!(event_bus.trigger_event(Box::new(Disconnected {})).is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![2, 4]);
+    assert_eq!(*states.lock().unwrap(), vec![false]);
+}
+#[test]
+fn consume_event() {
+    let mut event_bus = EventBusDefault::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let names = Arc::new(Mutex::new(Vec::new()));
+    let consumer = OrderedSubscriber::shared("consumer", order.clone(), Some(1));
+    let later = OrderedSubscriber::shared("later", order.clone(), None);
+    let audit: Arc<Mutex<Box<dyn Subscriber<dyn Event>>>> =
+        Arc::new(Mutex::new(Box::new(AuditSubscriber {
+            names: names.clone(),
+        })));
+
+    let _subscriptions = [
+        event_bus
+            .subscribe_event_with(&consumer, SubscriptionOptions::default().with_priority(1))
+            .unwrap(),
+        event_bus.subscribe_event(&later).unwrap(),
+        event_bus.subscribe_all(&audit).unwrap(),
+    ];
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    assert_eq!(*order.lock().unwrap(), vec!["consumer"]);
+    assert!(names.lock().unwrap().is_empty());
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(2)))
+        .is_ok());
+    assert_eq!(
+        *order.lock().unwrap(),
+        vec!["consumer", "consumer", "later"]
+    );
+    assert_eq!(names.lock().unwrap().len(), 1);
+}
+#[test]
+fn sticky_event() {
+    let mut event_bus = EventBusDefault::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+    let connection_subscriber = ConnectionSubscriber::shared(states.clone());
+
+    assert!(event_bus
+        .trigger_sticky_event(Box::new(SimpleEvent::new(1)))
+        .is_ok());
+    assert!(event_bus
+        .trigger_sticky_event(Box::new(SimpleEvent::new(2)))
+        .is_ok());
+    assert!(event_bus
+        .trigger_sticky_event(Box::new(Connected {}))
+        .is_ok());
+    assert!(event_bus
+        .trigger_sticky_event(Box::new(Disconnected {}))
+        .is_ok());
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(3)))
+        .is_ok());
+
+    // only the last sticky event of each type is delivered on subscription
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    let _connection_subscription = event_bus.subscribe_trait(&connection_subscriber).unwrap();
+    assert_eq!(*values.lock().unwrap(), vec![2]);
+    assert_eq!(*states.lock().unwrap(), vec![true, false]);
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(4)))
+        .is_ok());
+    assert_eq!(*values.lock().unwrap(), vec![2, 4]);
+}
+#[test]
+fn replay_history() {
+    let mut event_bus = EventBusDefault::with_history(3);
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let replayed = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+    let replayed_subscriber = SimpleSubscriber::shared(replayed.clone());
+
+    for value in 1..=4 {
+        assert!(event_bus
+            .trigger_event(Box::new(SimpleEvent::new(value)))
+            .is_ok());
+    }
+    assert!(event_bus.trigger_event(Box::new(OtherEvent {})).is_ok());
+
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    let _replayed_subscription = event_bus
+        .subscribe_event_with(
+            &replayed_subscriber,
+            SubscriptionOptions::<SimpleEvent>::default()
+                .with_replay(true)
+                .with_filter(|event| event.value != 3),
+        )
+        .unwrap();
+    assert!(values.lock().unwrap().is_empty());
+    assert_eq!(*replayed.lock().unwrap(), vec![4]);
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(5)))
+        .is_ok());
+    assert_eq!(*values.lock().unwrap(), vec![5]);
+    assert_eq!(*replayed.lock().unwrap(), vec![4, 5]);
+}
+#[test]
+fn event_from_another_origin() {
+    let mut event_bus = EventBusDefault::with_history(2);
+    let origins = Arc::new(Mutex::new(Vec::new()));
+    let subscriber: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(OriginSubscriber {
+            origins: origins.clone(),
+        })));
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+
+    let origin = Origin::new();
+    assert_ne!(origin, Origin::new());
+    assert!(event_bus
+        .trigger_event_from(Box::new(SimpleEvent::new(1)), origin)
+        .is_ok());
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent::new(2)))
+        .is_ok());
+    assert_eq!(*origins.lock().unwrap(), vec![Some(origin), None]);
+    assert_eq!(Origin::current(), None);
+
+    // the history keeps the origin
+    let replayed = Arc::new(Mutex::new(Vec::new()));
+    let replayed_subscriber: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(OriginSubscriber {
+            origins: replayed.clone(),
+        })));
+    let _replayed_subscription = event_bus
+        .subscribe_event_with(
+            &replayed_subscriber,
+            SubscriptionOptions::<SimpleEvent>::default().with_replay(true),
+        )
+        .unwrap();
+    assert_eq!(*replayed.lock().unwrap(), vec![Some(origin), None]);
+}
@@ -0,0 +1,251 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+
+use error::Error;
+use eventbus::event::{
+    ErrorCode, EventBus, Propagation, Subscriber, Subscription, SubscriptionRegistry,
+};
+use eventbus::queued::{EventBusQueued, QueuePolicy};
+use eventbus_derive::Event;
+use traitcast_derive::Castable;
+
+#[derive(Castable, Event)]
+struct SimpleEvent {
+    value: u32,
+}
+
+struct SimpleSubscriber {
+    values: Arc<Mutex<Vec<u32>>>,
+    threads: Arc<Mutex<Vec<ThreadId>>>,
+}
+impl Subscriber<SimpleEvent> for SimpleSubscriber {
+    fn notify(&mut self, event: &SimpleEvent) -> Propagation {
+        self.values.lock().unwrap().push(event.value);
+        self.threads.lock().unwrap().push(thread::current().id());
+        Propagation::Continue
+    }
+}
+
+/// Blocks the dispatcher thread in the first notification until it is
+/// released, so the queue can be filled deterministically
+struct BlockingSubscriber {
+    values: Arc<Mutex<Vec<u32>>>,
+    entered: Sender<()>,
+    release: Receiver<()>,
+}
+impl Subscriber<SimpleEvent> for BlockingSubscriber {
+    fn notify(&mut self, event: &SimpleEvent) -> Propagation {
+        self.entered.send(()).ok();
+        self.release.recv().ok();
+        self.values.lock().unwrap().push(event.value);
+        Propagation::Continue
+    }
+}
+
+/// Panics on the first notification
+struct PanickingSubscriber {}
+impl Subscriber<SimpleEvent> for PanickingSubscriber {
+    fn notify(&mut self, _: &SimpleEvent) -> Propagation {
+        panic!("subscriber failed");
+    }
+}
+
+/// Bus, subscription, delivered values, entered and release channels
+type BlockingBus = (
+    EventBusQueued,
+    (Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>>, Subscription),
+    Arc<Mutex<Vec<u32>>>,
+    Receiver<()>,
+    Sender<()>,
+);
+
+fn blocking_bus(capacity: usize, policy: QueuePolicy) -> BlockingBus {
+    let mut event_bus = EventBusQueued::new(capacity, policy);
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let (entered_sender, entered) = channel();
+    let (release, release_receiver) = channel();
+    let subscriber: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(BlockingSubscriber {
+            values: values.clone(),
+            entered: entered_sender,
+            release: release_receiver,
+        })));
+    let subscription = event_bus.subscribe_event(&subscriber).unwrap();
+
+    // the dispatcher holds the first event, the queue is empty afterwards
+    event_bus
+        .trigger_event(Box::new(SimpleEvent { value: 0 }))
+        .unwrap();
+    entered.recv().unwrap();
+
+    // the bus only keeps a weak reference, so the caller keeps the subscriber
+    (
+        event_bus,
+        (subscriber, subscription),
+        values,
+        entered,
+        release,
+    )
+}
+
+#[test]
+fn delivers_on_dispatcher_thread() {
+    let mut event_bus = EventBusQueued::new(16, QueuePolicy::Block);
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let threads = Arc::new(Mutex::new(Vec::new()));
+    let subscriber: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(SimpleSubscriber {
+            values: values.clone(),
+            threads: threads.clone(),
+        })));
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+
+    for value in 1..=3 {
+        assert!(event_bus
+            .trigger_event(Box::new(SimpleEvent { value }))
+            .is_ok());
+    }
+    assert!(event_bus.flush().is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![1, 2, 3]);
+    assert!(threads
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|id| *id != thread::current().id()));
+}
+#[test]
+fn drop_oldest_when_full() {
+    let (mut event_bus, _subscribed, values, _entered, release) =
+        blocking_bus(2, QueuePolicy::DropOldest);
+
+    for value in 1..=4 {
+        assert!(event_bus
+            .trigger_event(Box::new(SimpleEvent { value }))
+            .is_ok());
+    }
+    for _ in 0..3 {
+        release.send(()).unwrap();
+    }
+    assert!(event_bus.flush().is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![0, 3, 4]);
+}
+#[test]
+fn error_when_full() {
+    let (mut event_bus, _subscribed, values, _entered, release) =
+        blocking_bus(1, QueuePolicy::Error);
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent { value: 1 }))
+        .is_ok());
+    assert_eq!(
+        event_bus.trigger_event(Box::new(SimpleEvent { value: 2 })),
+        Err(Error::new(ErrorCode::QueueFull, ""))
+    );
+    for _ in 0..2 {
+        release.send(()).unwrap();
+    }
+    assert!(event_bus.flush().is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![0, 1]);
+}
+#[test]
+fn block_when_full() {
+    let (mut event_bus, _subscribed, values, entered, release) =
+        blocking_bus(1, QueuePolicy::Block);
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent { value: 1 }))
+        .is_ok());
+    let releaser = thread::spawn(move || {
+        release.send(()).unwrap();
+        entered.recv().unwrap();
+        release.send(()).unwrap();
+        entered.recv().unwrap();
+        release.send(()).unwrap();
+    });
+    // returns as soon as the dispatcher took the first queued event
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent { value: 2 }))
+        .is_ok());
+    assert!(event_bus.flush().is_ok());
+    releaser.join().unwrap();
+
+    assert_eq!(*values.lock().unwrap(), vec![0, 1, 2]);
+}
+#[test]
+fn shutdown_drains_queue() {
+    let (mut event_bus, _subscribed, values, _entered, release) =
+        blocking_bus(4, QueuePolicy::Error);
+
+    for value in 1..=2 {
+        assert!(event_bus
+            .trigger_event(Box::new(SimpleEvent { value }))
+            .is_ok());
+    }
+    for _ in 0..3 {
+        release.send(()).unwrap();
+    }
+    assert!(event_bus.shutdown().is_ok());
+    assert_eq!(*values.lock().unwrap(), vec![0, 1, 2]);
+
+    assert_eq!(
+        event_bus.trigger_event(Box::new(SimpleEvent { value: 3 })),
+        Err(Error::new(ErrorCode::ShutDown, ""))
+    );
+}
+#[test]
+fn sticky_event_on_dispatcher_thread() {
+    let mut event_bus = EventBusQueued::with_history(16, QueuePolicy::Block, 4);
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let threads = Arc::new(Mutex::new(Vec::new()));
+    let subscriber: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(SimpleSubscriber {
+            values: values.clone(),
+            threads: threads.clone(),
+        })));
+
+    for value in 1..=2 {
+        assert!(event_bus
+            .trigger_sticky_event(Box::new(SimpleEvent { value }))
+            .is_ok());
+    }
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent { value: 3 }))
+        .is_ok());
+    assert!(event_bus.flush().is_ok());
+
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent { value: 4 }))
+        .is_ok());
+    assert!(event_bus.flush().is_ok());
+
+    assert_eq!(*values.lock().unwrap(), vec![2, 4]);
+    assert!(threads
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|id| *id != thread::current().id()));
+}
+#[test]
+fn shut_down_when_a_subscriber_panics() {
+    let mut event_bus = EventBusQueued::new(1, QueuePolicy::Block);
+    let subscriber: Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> =
+        Arc::new(Mutex::new(Box::new(PanickingSubscriber {})));
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent { value: 1 }))
+        .is_ok());
+    // fails instead of waiting for the dispatcher thread forever
+    assert_eq!(event_bus.flush(), Err(Error::new(ErrorCode::ShutDown, "")));
+    for value in 2..=3 {
+        assert_eq!(
+            event_bus.trigger_event(Box::new(SimpleEvent { value })),
+            Err(Error::new(ErrorCode::ShutDown, ""))
+        );
+    }
+}
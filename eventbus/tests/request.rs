@@ -0,0 +1,193 @@
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use error::Error;
+use eventbus::event::{ErrorCode, EventBusDefault, Propagation, Subscriber, SubscriptionRegistry};
+use eventbus::queued::{EventBusQueued, QueuePolicy};
+use eventbus::request::{Request, RequestBus, Responder};
+use eventbus_derive::Event;
+use traitcast_derive::Castable;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Castable, Event)]
+struct Square {
+    value: u32,
+}
+impl Request for Square {
+    type Response = u32;
+}
+
+struct SquareResponder {}
+impl SquareResponder {
+    pub fn shared() -> Arc<Mutex<Box<dyn Responder<Square>>>> {
+        Arc::new(Mutex::new(Box::new(SquareResponder {})))
+    }
+}
+impl Responder<Square> for SquareResponder {
+    fn respond(&mut self, request: &Square) -> u32 {
+        request.value * request.value
+    }
+}
+
+/// Responds only after it is released
+struct BlockingResponder {
+    release: Receiver<()>,
+}
+impl Responder<Square> for BlockingResponder {
+    fn respond(&mut self, request: &Square) -> u32 {
+        self.release.recv().ok();
+        request.value
+    }
+}
+
+/// Stops the dispatcher thread
+struct PanickingResponder {}
+impl Responder<Square> for PanickingResponder {
+    fn respond(&mut self, _: &Square) -> u32 {
+        panic!("responder failed");
+    }
+}
+
+struct SquareSubscriber {
+    values: Arc<Mutex<Vec<u32>>>,
+}
+impl Subscriber<Square> for SquareSubscriber {
+    fn notify(&mut self, event: &Square) -> Propagation {
+        self.values.lock().unwrap().push(event.value);
+        Propagation::Continue
+    }
+}
+
+#[test]
+fn request_response() {
+    let mut event_bus = EventBusDefault::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let responder = SquareResponder::shared();
+    let subscriber: Arc<Mutex<Box<dyn Subscriber<Square>>>> =
+        Arc::new(Mutex::new(Box::new(SquareSubscriber {
+            values: values.clone(),
+        })));
+
+    let _responder_subscription = event_bus.respond_to(&responder).unwrap();
+    let _subscription = event_bus.subscribe_event(&subscriber).unwrap();
+    assert_eq!(event_bus.request(Square { value: 3 }, TIMEOUT), Ok(9));
+    let response = event_bus.send_request(Square { value: 4 }).unwrap();
+    assert_eq!(response.wait(TIMEOUT), Ok(16));
+
+    // subscribers see requests like any other event
+    assert_eq!(*values.lock().unwrap(), vec![3, 4]);
+}
+#[test]
+fn no_responder() {
+    let mut event_bus = EventBusDefault::new();
+    assert_eq!(
+        event_bus.request(Square { value: 3 }, TIMEOUT).err(),
+        Some(Error::new(ErrorCode::NoResponder, ""))
+    );
+
+    let responder = SquareResponder::shared();
+    let subscription = event_bus.respond_to(&responder).unwrap();
+    drop(subscription);
+    assert_eq!(
+        event_bus.request(Square { value: 3 }, TIMEOUT).err(),
+        Some(Error::new(ErrorCode::NoResponder, ""))
+    );
+
+    let _subscription = event_bus.respond_to(&responder).unwrap();
+    drop(responder);
+    assert_eq!(
+        event_bus.request(Square { value: 3 }, TIMEOUT).err(),
+        Some(Error::new(ErrorCode::NoResponder, ""))
+    );
+}
+#[test]
+fn multiple_responders() {
+    let mut event_bus = EventBusDefault::new();
+    let responder = SquareResponder::shared();
+    let other_responder = SquareResponder::shared();
+
+    let subscription = event_bus.respond_to(&responder).unwrap();
+    assert_eq!(
+        event_bus.respond_to(&other_responder).err(),
+        Some(Error::new(ErrorCode::MultipleResponders, ""))
+    );
+    // the responder can be replaced after it unsubscribed
+    assert!(subscription.unsubscribe().is_ok());
+    let _subscription = event_bus.respond_to(&other_responder).unwrap();
+    assert_eq!(event_bus.request(Square { value: 2 }, TIMEOUT), Ok(4));
+}
+#[test]
+fn request_on_queued_bus() {
+    let mut event_bus = EventBusQueued::new(16, QueuePolicy::Block);
+    let responder = SquareResponder::shared();
+
+    let _subscription = event_bus.respond_to(&responder).unwrap();
+    let responses: Vec<_> = (1..=3)
+        .map(|value| event_bus.send_request(Square { value }).unwrap())
+        .collect();
+    let responses: Vec<_> = responses
+        .into_iter()
+        .map(|response| response.wait(TIMEOUT).unwrap())
+        .collect();
+    assert_eq!(responses, vec![1, 4, 9]);
+}
+#[test]
+fn request_timeout() {
+    let mut event_bus = EventBusQueued::new(16, QueuePolicy::Block);
+    let (release, release_receiver) = channel();
+    let responder: Arc<Mutex<Box<dyn Responder<Square>>>> =
+        Arc::new(Mutex::new(Box::new(BlockingResponder {
+            release: release_receiver,
+        })));
+
+    let _subscription = event_bus.respond_to(&responder).unwrap();
+    assert_eq!(
+        event_bus
+            .request(Square { value: 3 }, Duration::from_millis(50))
+            .err(),
+        Some(Error::new(ErrorCode::Timeout, ""))
+    );
+    release.send(()).unwrap();
+    assert!(event_bus.flush().is_ok());
+}
+#[test]
+fn request_dropped_from_a_full_queue() {
+    let mut event_bus = EventBusQueued::new(1, QueuePolicy::DropOldest);
+    let (release, release_receiver) = channel();
+    let responder: Arc<Mutex<Box<dyn Responder<Square>>>> =
+        Arc::new(Mutex::new(Box::new(BlockingResponder {
+            release: release_receiver,
+        })));
+
+    let _subscription = event_bus.respond_to(&responder).unwrap();
+    let _first = event_bus.send_request(Square { value: 1 }).unwrap();
+    let second = event_bus.send_request(Square { value: 2 }).unwrap();
+    let third = event_bus.send_request(Square { value: 3 }).unwrap();
+    // fails right away instead of running into the timeout
+    assert_eq!(
+        second.wait(TIMEOUT).err(),
+        Some(Error::new(ErrorCode::QueueFull, ""))
+    );
+    release.send(()).unwrap();
+    release.send(()).unwrap();
+    assert_eq!(third.wait(TIMEOUT), Ok(3));
+}
+#[test]
+fn request_dropped_on_shutdown() {
+    let mut event_bus = EventBusQueued::new(16, QueuePolicy::Block);
+    let responder: Arc<Mutex<Box<dyn Responder<Square>>>> =
+        Arc::new(Mutex::new(Box::new(PanickingResponder {})));
+
+    let _subscription = event_bus.respond_to(&responder).unwrap();
+    let _first = event_bus.send_request(Square { value: 0 }).unwrap();
+    let second = event_bus.send_request(Square { value: 2 }).unwrap();
+    // the dispatcher panicked on the first request and never delivers the second
+    assert!(event_bus.shutdown().is_err());
+    drop(event_bus);
+    assert_eq!(
+        second.wait(TIMEOUT).err(),
+        Some(Error::new(ErrorCode::ShutDown, ""))
+    );
+}
@@ -0,0 +1,106 @@
+use std::sync::{Arc, Mutex};
+
+use di::registry::{Registry, SimpleSession};
+use di_derive::inject;
+use error::Error;
+use eventbus::event::{ErrorCode, Propagation, Subscriber, Subscription, SubscriptionRegistry};
+use eventbus::service::{EventBusService, EventBusServiceDefault};
+use eventbus_derive::Event;
+use traitcast_derive::Castable;
+
+#[derive(Castable, Event)]
+struct SimpleEvent {
+    value: u32,
+}
+
+struct SimpleSubscriber {
+    values: Arc<Mutex<Vec<u32>>>,
+}
+impl SimpleSubscriber {
+    pub fn shared(values: Arc<Mutex<Vec<u32>>>) -> Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>> {
+        Arc::new(Mutex::new(Box::new(SimpleSubscriber { values })))
+    }
+}
+impl Subscriber<SimpleEvent> for SimpleSubscriber {
+    fn notify(&mut self, event: &SimpleEvent) -> Propagation {
+        self.values.lock().unwrap().push(event.value);
+        Propagation::Continue
+    }
+}
+
+#[inject(event_bus)]
+fn subscribe(
+    #[session] session: &dyn Session,
+    event_bus: &dyn EventBusService,
+    subscriber: &Arc<Mutex<Box<dyn Subscriber<SimpleEvent>>>>,
+) -> Subscription {
+    event_bus
+        .subscriptions()
+        .subscribe_event(subscriber)
+        .unwrap()
+}
+
+#[inject(event_bus)]
+fn trigger(#[session] session: &dyn Session, event_bus: &mut dyn EventBusService, value: u32) {
+    assert!(event_bus
+        .trigger_event(Box::new(SimpleEvent { value }))
+        .is_ok());
+}
+
+// the registry is global, so all checks share one test
+#[test]
+fn one_bus_per_session() {
+    EventBusServiceDefault::register().unwrap();
+    let session = SimpleSession::new();
+    let other_session = SimpleSession::new();
+    let values = Arc::new(Mutex::new(Vec::new()));
+    let other_values = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = SimpleSubscriber::shared(values.clone());
+    let other_subscriber = SimpleSubscriber::shared(other_values.clone());
+
+    let subscription = subscribe(&session, &subscriber).unwrap();
+    let _other_subscription = subscribe(&other_session, &other_subscriber).unwrap();
+    trigger(&session, 1).unwrap();
+    trigger(&other_session, 2).unwrap();
+    trigger(&session, 3).unwrap();
+    assert_eq!(*values.lock().unwrap(), vec![1, 3]);
+    assert_eq!(*other_values.lock().unwrap(), vec![2]);
+
+    // clearing a session ends the subscriptions of its bus only
+    assert!(Registry::global().clear_session(&session).is_ok());
+    trigger(&session, 4).unwrap();
+    trigger(&other_session, 5).unwrap();
+    assert_eq!(*values.lock().unwrap(), vec![1, 3]);
+    assert_eq!(*other_values.lock().unwrap(), vec![2, 5]);
+    assert_eq!(
+        subscription.unsubscribe(),
+        Err(Error::new(ErrorCode::NotSubscribed, ""))
+    );
+
+    EventBusServiceDefault::unregister().unwrap();
+}
+
+#[test]
+fn one_bus_per_session_of_a_container() {
+    let registry = Registry::default();
+    EventBusServiceDefault::register_in(&registry).unwrap();
+    let session = SimpleSession::new();
+    let other_session = SimpleSession::new();
+
+    let bus = registry
+        .get_service::<dyn EventBusService>(&session)
+        .unwrap();
+    let again = registry
+        .get_service::<dyn EventBusService>(&session)
+        .unwrap();
+    let other_bus = registry
+        .get_service::<dyn EventBusService>(&other_session)
+        .unwrap();
+    assert!(bus.ptr_eq(&again));
+    assert!(!bus.ptr_eq(&other_bus));
+
+    EventBusServiceDefault::unregister_from(&registry).unwrap();
+    assert!(registry
+        .get_service::<dyn EventBusService>(&session)
+        .is_err());
+}
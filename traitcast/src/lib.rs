@@ -1,55 +1,58 @@
 /*!
  *
  */
-use std::any::TypeId;
+use std::any::{Any, TypeId};
+use std::sync::Arc;
 
-/// This is the layout of a trait object in rust
-/// The trait to be implemented by every struct that wants to support
-pub struct VTable(*const ());
-impl VTable {
-    pub fn none() -> VTable {
-        VTable(std::ptr::null())
-    }
-}
-pub struct TraitObject {
-    pub data: *const (),
-    pub vtable: VTable,
+/// Casts of a Castable to the type U, returned by Castable::query_caster for
+/// TypeId::of::<U>(). The casts check the type of the castable, so they
+/// return None for other types than the one that returned the caster.
+pub struct Caster<U: ?Sized + 'static> {
+    pub cast_ref: fn(&dyn Castable) -> Option<&U>,
+    pub cast_mut: fn(&mut dyn Castable) -> Option<&mut U>,
+    pub cast_arc: fn(Arc<dyn Castable + Sync>) -> Option<Arc<U>>,
 }
 
 /// Trait as staring point for any Cast
-pub trait Castable: Send {
-    fn query_vtable(&self, id: TypeId) -> Option<VTable>;
+pub trait Castable: Any + Send {
+    /// Caster<U> for the type U with the given id, if the implementing type
+    /// can be cast to it. #[derive(Castable)] returns static casters.
+    fn query_caster(&self, id: TypeId) -> Option<&'static dyn Any>;
 }
 /// Implementation of the cast
 impl dyn Castable {
     /// reference cast
     pub fn query_ref<U: ?Sized + 'static>(&self) -> Option<&U> {
-        if let Some(vtable) = self.query_vtable(::std::any::TypeId::of::<U>()) {
-            unsafe {
-                let data = self as *const Self;
-                let u = TraitObject {
-                    data: data as *const (),
-                    vtable: vtable,
-                };
-                Some(*::std::mem::transmute::<_, &&U>(&u))
-            }
-        } else {
-            None
-        }
+        let caster = self
+            .query_caster(TypeId::of::<U>())?
+            .downcast_ref::<Caster<U>>()?;
+        (caster.cast_ref)(self)
     }
     /// mutable cast
     pub fn query_mut<U: ?Sized + 'static>(&mut self) -> Option<&mut U> {
-        if let Some(vtable) = self.query_vtable(::std::any::TypeId::of::<U>()) {
-            unsafe {
-                let data = self as *mut Self;
-                let mut u = TraitObject {
-                    data: data as *mut (),
-                    vtable: vtable,
-                };
-                Some(*::std::mem::transmute::<_, &mut &mut U>(&mut u))
-            }
-        } else {
-            None
-        }
+        let caster = self
+            .query_caster(TypeId::of::<U>())?
+            .downcast_ref::<Caster<U>>()?;
+        (caster.cast_mut)(self)
+    }
+}
+/// Implementation of the shared cast
+impl dyn Castable + Sync {
+    /// shared reference cast
+    pub fn query_arc<U: ?Sized + 'static>(self: Arc<Self>) -> Option<Arc<U>> {
+        let caster = self
+            .query_caster(TypeId::of::<U>())?
+            .downcast_ref::<Caster<U>>()?;
+        (caster.cast_arc)(self)
+    }
+}
+
+/// Arc of the castable as its own type T, for Caster::cast_arc
+pub fn downcast_arc<T: Castable>(castable: Arc<dyn Castable + Sync>) -> Option<Arc<T>> {
+    if !(&*castable as &dyn Any).is::<T>() {
+        return None;
     }
+    // SAFETY: the instance is a T, so the allocation of the Arc is the one of
+    // an Arc<T>
+    Some(unsafe { Arc::from_raw(Arc::into_raw(castable) as *const T) })
 }
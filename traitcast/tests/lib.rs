@@ -1,6 +1,7 @@
-use std::any::TypeId;
+use std::any::{Any, TypeId};
+use std::sync::Arc;
 
-use traitcast::{Castable, TraitObject, VTable};
+use traitcast::{Castable, Caster};
 trait Service: Castable {}
 trait SimpleService {
     fn foo(&self) -> bool;
@@ -24,13 +25,30 @@ impl SimpleService for ServiceImpl {
 impl ServiceImpl {}
 impl Service for ServiceImpl {}
 impl Castable for ServiceImpl {
-    fn query_vtable(&self, id: TypeId) -> Option<VTable> {
+    fn query_caster(&self, id: TypeId) -> Option<&'static dyn Any> {
         if id == ::std::any::TypeId::of::<ServiceImpl>() {
-            Some(VTable::none())
+            Some(&Caster::<ServiceImpl> {
+                cast_ref: |castable| (castable as &dyn Any).downcast_ref::<ServiceImpl>(),
+                cast_mut: |castable| (castable as &mut dyn Any).downcast_mut::<ServiceImpl>(),
+                cast_arc: traitcast::downcast_arc::<ServiceImpl>,
+            })
         } else if id == ::std::any::TypeId::of::<dyn SimpleService>() {
-            let x = ::std::ptr::null::<ServiceImpl>() as *const dyn SimpleService;
-            let vt = unsafe { ::std::mem::transmute::<_, TraitObject>(x).vtable };
-            Some(vt)
+            Some(&Caster::<dyn SimpleService> {
+                cast_ref: |castable| {
+                    (castable as &dyn Any)
+                        .downcast_ref::<ServiceImpl>()
+                        .map(|service| service as &dyn SimpleService)
+                },
+                cast_mut: |castable| {
+                    (castable as &mut dyn Any)
+                        .downcast_mut::<ServiceImpl>()
+                        .map(|service| service as &mut dyn SimpleService)
+                },
+                cast_arc: |castable| {
+                    traitcast::downcast_arc::<ServiceImpl>(castable)
+                        .map(|service| service as Arc<dyn SimpleService>)
+                },
+            })
         } else {
             None
         }
@@ -63,3 +81,31 @@ fn cast_ref_failed() {
     let other_service = castable.query_ref::<dyn OtherService>();
     assert_eq!(other_service.is_none(), true);
 }
+#[test]
+fn cast_arc_succeeded() {
+    let castable: Arc<dyn Castable + Sync> = Arc::new(ServiceImpl {});
+    let simple_service = castable.clone().query_arc::<dyn SimpleService>();
+    assert!(simple_service.unwrap().foo());
+    assert!(castable.clone().query_arc::<dyn OtherService>().is_none());
+    assert!(castable.query_arc::<ServiceImpl>().is_some());
+}
+
+/// Hands out the casters of ServiceImpl
+struct Impostor {}
+impl Castable for Impostor {
+    fn query_caster(&self, id: TypeId) -> Option<&'static dyn Any> {
+        ServiceImpl {}.query_caster(id)
+    }
+}
+
+#[test]
+fn cast_with_caster_of_other_type_failed() {
+    let mut impostor = Impostor {};
+    let castable = &mut impostor as &mut dyn Castable;
+    assert!(castable.query_ref::<dyn SimpleService>().is_none());
+    assert!(castable.query_mut::<ServiceImpl>().is_none());
+
+    let castable: Arc<dyn Castable + Sync> = Arc::new(Impostor {});
+    assert!(castable.clone().query_arc::<dyn SimpleService>().is_none());
+    assert!(castable.query_arc::<ServiceImpl>().is_none());
+}
@@ -1,58 +1,153 @@
 use proc_macro::{self, TokenStream};
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput, Meta, NestedMeta};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, parse_quote, DeriveInput, GenericParam, Token, TypeParamBound};
 
+/// One entry of #[Traits(...)]: a trait, optionally with marker traits such as
+/// Foo<T> + Sync
+struct Cast {
+    bounds: Punctuated<TypeParamBound, Token![+]>,
+}
+
+impl Parse for Cast {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let bounds = Punctuated::parse_separated_nonempty(input)?;
+        for bound in &bounds {
+            if let TypeParamBound::Lifetime(lifetime) = bound {
+                return Err(syn::Error::new_spanned(
+                    lifetime,
+                    "[derive(Castable)]: lifetimes are not supported, casts are 'static",
+                ));
+            }
+        }
+        Ok(Cast { bounds })
+    }
+}
+
+impl Cast {
+    /// Trait object types the entry casts to: the trait alone and with every
+    /// combination of its markers. Send is always added, since Castable
+    /// requires it.
+    fn targets(&self) -> Vec<proc_macro2::TokenStream> {
+        let mut bounds = self.bounds.iter();
+        let iface = bounds.next().expect("parse_separated_nonempty");
+        let mut markers: Vec<&TypeParamBound> = bounds.collect();
+        let send: TypeParamBound = parse_quote!(::std::marker::Send);
+        if !markers.iter().any(|marker| is_send(marker)) {
+            markers.push(&send);
+        }
+
+        (0..1usize << markers.len())
+            .map(|combination| {
+                let chosen = markers
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| combination & (1 << index) != 0)
+                    .map(|(_, marker)| marker);
+                quote!((dyn #iface #(+ #chosen)*))
+            })
+            .collect()
+    }
+}
+
+fn is_send(bound: &TypeParamBound) -> bool {
+    match bound {
+        TypeParamBound::Trait(bound) => bound
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Send"),
+        TypeParamBound::Lifetime(_) => false,
+    }
+}
+
+/// Implements Castable for the struct, so it can be cast to itself and to the
+/// traits listed in #[Traits(...)]. A trait may be a path with generic
+/// arguments, like #[Traits(crate::events::Subscriber<Login>)]. Casts to
+/// dyn Trait + Send are always available, other marker traits are listed
+/// with the trait, like #[Traits(Foo + Sync)].
+///
+/// Generic structs keep their bounds. Castable is implemented for the
+/// instances that are 'static, Send and implement the listed marker traits.
 #[proc_macro_derive(Castable, attributes(Traits))]
 pub fn derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(output) => output.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut casts: Vec<Cast> = Vec::new();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("Traits"))
+    {
+        casts.extend(attr.parse_args_with(Punctuated::<Cast, Token![,]>::parse_terminated)?);
+    }
+    let targets: Vec<proc_macro2::TokenStream> = casts.iter().flat_map(Cast::targets).collect();
+
     let struct_type = &input.ident;
+    let mut generics = input.generics.clone();
+    if !generics.params.is_empty() {
+        let (_, ty_generics, _) = input.generics.split_for_impl();
+        for param in generics.params.iter_mut() {
+            match param {
+                GenericParam::Type(param) => param.bounds.push(parse_quote!('static)),
+                GenericParam::Lifetime(param) => param.bounds.push(parse_quote!('static)),
+                GenericParam::Const(_) => (),
+            }
+        }
+        let markers = casts.iter().flat_map(|cast| cast.bounds.iter().skip(1));
+        generics
+            .make_where_clause()
+            .predicates
+            .push(parse_quote!(#struct_type #ty_generics: ::std::marker::Send #(+ #markers)*));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let mut ifaces = quote!();
-
-    let attrs = input.attrs;
-    attrs.iter().for_each(|attr| match attr.path.get_ident() {
-        Some(ident) => match ident.to_string().as_str() {
-            "Traits" => match attr.parse_meta().unwrap() {
-                Meta::List(list) => list.nested.iter().for_each(|data| match data {
-                    NestedMeta::Meta(meta) => match meta {
-                        Meta::Path(path) => {
-                            let iface = path
-                                .get_ident()
-                                .expect("[derivce(Callable)]: no ident found");
-                            let token = quote!(
-                                else if id == ::std::any::TypeId::of::<dyn #iface>() {
-                                    let x = ::std::ptr::null::<#struct_type>() as *const dyn #iface;
-                                    let vt = unsafe { ::std::mem::transmute::<_, traitcast::TraitObject>(x).vtable };
-                                    return Some(vt);
-                                }
-                            );
-                            ifaces = quote!(#ifaces #token);
-                        }
-                        _ => panic!("[derivce(Callable)]: Unsupported nested meta meta type"),
-                    },
-                    _ => panic!("[derivce(Callable)]: Unsupported nested meta type"),
-                }),
-                _ => panic!("[derivce(Callable)]: Unsupported meta type"),
-            },
-            x => panic!("[derivce(Callable)]: Unsupported attribute: {}", x),
-        },
-        None => panic!("[derivce(Callable)]: Found attribute without ident"),
-    });
-
-    let output = quote! {
-        impl traitcast::Castable for #struct_type {
-            fn query_vtable(&self, id: ::std::any::TypeId) -> Option<traitcast::VTable> {
-                if id == ::std::any::TypeId::of::<#struct_type>() {
-                    return Some(traitcast::VTable::none());
-                }
-                #ifaces
-                else {
-                    None
+    Ok(quote! {
+        impl #impl_generics traitcast::Castable for #struct_type #ty_generics #where_clause {
+            fn query_caster(
+                &self,
+                id: ::std::any::TypeId,
+            ) -> Option<&'static dyn ::std::any::Any> {
+                if id == ::std::any::TypeId::of::<Self>() {
+                    return Some(&traitcast::Caster::<Self> {
+                        cast_ref: |castable| {
+                            (castable as &dyn ::std::any::Any).downcast_ref::<Self>()
+                        },
+                        cast_mut: |castable| {
+                            (castable as &mut dyn ::std::any::Any).downcast_mut::<Self>()
+                        },
+                        cast_arc: traitcast::downcast_arc::<Self>,
+                    });
                 }
+                #(
+                    if id == ::std::any::TypeId::of::<#targets>() {
+                        return Some(&traitcast::Caster::<#targets> {
+                            cast_ref: |castable| {
+                                (castable as &dyn ::std::any::Any)
+                                    .downcast_ref::<Self>()
+                                    .map(|this| this as &#targets)
+                            },
+                            cast_mut: |castable| {
+                                (castable as &mut dyn ::std::any::Any)
+                                    .downcast_mut::<Self>()
+                                    .map(|this| this as &mut #targets)
+                            },
+                            cast_arc: |castable| {
+                                traitcast::downcast_arc::<Self>(castable)
+                                    .map(|this| this as ::std::sync::Arc<#targets>)
+                            },
+                        });
+                    }
+                )*
+                None
             }
         }
-    };
-
-    //println!("{}", output.to_string().as_str());
-    output.into()
+    })
 }
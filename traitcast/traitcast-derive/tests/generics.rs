@@ -0,0 +1,126 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use traitcast::Castable;
+use traitcast_derive::Castable;
+
+mod events {
+    pub trait Named {
+        fn name(&self) -> String;
+    }
+
+    pub struct Login;
+    pub struct Logout;
+}
+use events::{Login, Logout, Named};
+
+trait Handler<E> {
+    fn handle(&self, event: &E) -> String;
+}
+
+trait Describe {
+    fn describe(&self) -> String;
+}
+
+#[derive(Castable)]
+#[Traits(events::Named, Handler<Login>)]
+#[allow(dead_code)]
+struct Auditor {}
+impl Named for Auditor {
+    fn name(&self) -> String {
+        "auditor".to_owned()
+    }
+}
+impl Handler<Login> for Auditor {
+    fn handle(&self, _: &Login) -> String {
+        "login".to_owned()
+    }
+}
+impl Handler<Logout> for Auditor {
+    fn handle(&self, _: &Logout) -> String {
+        "logout".to_owned()
+    }
+}
+
+#[derive(Castable)]
+#[Traits(Describe + Sync, Handler<T>)]
+struct Wrapper<T: Display>
+where
+    T: Clone,
+{
+    value: T,
+}
+impl<T: Display + Clone> Describe for Wrapper<T> {
+    fn describe(&self) -> String {
+        format!("wrapped {}", self.value)
+    }
+}
+impl<T: Display + Clone> Handler<T> for Wrapper<T> {
+    fn handle(&self, event: &T) -> String {
+        format!("{} and {}", self.value, event)
+    }
+}
+
+#[test]
+fn cast_to_a_trait_given_by_path() {
+    let auditor = Auditor {};
+    let castable = &auditor as &dyn Castable;
+    assert_eq!(castable.query_ref::<dyn Named>().unwrap().name(), "auditor");
+}
+
+#[test]
+fn cast_to_a_generic_trait_instance() {
+    let auditor = Auditor {};
+    let castable = &auditor as &dyn Castable;
+    let handler = castable.query_ref::<dyn Handler<Login>>().unwrap();
+    assert_eq!(handler.handle(&Login), "login");
+    // implemented, but not listed in #[Traits(...)]
+    assert!(castable.query_ref::<dyn Handler<Logout>>().is_none());
+}
+
+#[test]
+fn cast_a_generic_struct() {
+    let mut wrapper = Wrapper { value: 7u32 };
+    let castable = &mut wrapper as &mut dyn Castable;
+    assert_eq!(
+        castable.query_ref::<dyn Describe>().unwrap().describe(),
+        "wrapped 7"
+    );
+    assert_eq!(
+        castable.query_ref::<dyn Handler<u32>>().unwrap().handle(&8),
+        "7 and 8"
+    );
+    assert!(castable.query_ref::<dyn Handler<String>>().is_none());
+
+    castable.query_mut::<Wrapper<u32>>().unwrap().value = 9;
+    assert!(castable.query_ref::<Wrapper<String>>().is_none());
+    assert_eq!(wrapper.describe(), "wrapped 9");
+}
+
+#[test]
+fn cast_to_a_trait_with_send_and_sync() {
+    let auditor = Auditor {};
+    let castable = &auditor as &dyn Castable;
+    let named = castable.query_ref::<dyn Named + Send>().unwrap();
+    assert_eq!(named.name(), "auditor");
+    assert!(castable.query_ref::<dyn Named + Sync>().is_none());
+
+    let wrapper = Wrapper {
+        value: "text".to_owned(),
+    };
+    let castable = &wrapper as &dyn Castable;
+    let describe = castable.query_ref::<dyn Describe + Sync>().unwrap();
+    assert_eq!(describe.describe(), "wrapped text");
+    assert!(castable.query_ref::<dyn Describe + Send + Sync>().is_some());
+    assert!(castable.query_ref::<dyn Describe + Send>().is_some());
+    assert!(castable.query_ref::<dyn Handler<String> + Sync>().is_none());
+}
+
+#[test]
+fn cast_an_arc() {
+    let castable: Arc<dyn Castable + Sync> = Arc::new(Wrapper { value: 3u8 });
+    let describe = castable.clone().query_arc::<dyn Describe + Sync>().unwrap();
+    assert_eq!(describe.describe(), "wrapped 3");
+    assert!(castable.clone().query_arc::<dyn Named>().is_none());
+    assert_eq!(castable.query_arc::<Wrapper<u8>>().unwrap().value, 3);
+}